@@ -0,0 +1,108 @@
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use starlight_axum::logger::{CustomLogFormatter, LogTimezone, ModulePathStyle, TimestampFormat};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::Dispatch;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Counts allocations, to check that formatting an event does not allocate.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const WARMUP_EVENTS: usize = 100;
+const COUNTED_EVENTS: usize = 1_000;
+
+fn dispatch(formatter: CustomLogFormatter) -> Dispatch {
+    let subscriber = tracing_subscriber::registry().with(
+        tracing_subscriber::fmt::layer()
+            .event_format(formatter)
+            .with_writer(std::io::sink),
+    );
+    Dispatch::new(subscriber)
+}
+
+fn log_event(iteration: usize) {
+    tracing::info!(iteration, user = "alice", "handled request in {}ms", 12);
+}
+
+fn formatters() -> Vec<(&'static str, CustomLogFormatter)> {
+    vec![
+        ("local_default", CustomLogFormatter::new()),
+        (
+            "utc_rfc3339",
+            CustomLogFormatter::new()
+                .with_timezone(LogTimezone::Utc)
+                .with_timestamp_format(TimestampFormat::Rfc3339),
+        ),
+        (
+            "named_epoch_millis",
+            CustomLogFormatter::new()
+                .with_timezone(LogTimezone::named("Europe/Berlin").expect("known timezone"))
+                .with_timestamp_format(TimestampFormat::EpochMillis),
+        ),
+        (
+            "spans_full_module_path",
+            CustomLogFormatter::new()
+                .with_thread_id(true)
+                .with_module_path(Some(ModulePathStyle::Full))
+                .with_spans(true),
+        ),
+    ]
+}
+
+fn allocations_per_event(dispatch: &Dispatch) -> f64 {
+    tracing::dispatcher::with_default(dispatch, || {
+        let span = tracing::info_span!("request", id = 1);
+        let _entered = span.enter();
+        // The first events grow the fmt layer's thread-local buffer.
+        (0..WARMUP_EVENTS).for_each(log_event);
+
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        (0..COUNTED_EVENTS).for_each(log_event);
+        let after = ALLOCATIONS.load(Ordering::Relaxed);
+        (after - before) as f64 / COUNTED_EVENTS as f64
+    })
+}
+
+fn bench_format_event(c: &mut Criterion) {
+    let mut group = c.benchmark_group("format_event");
+    for (name, formatter) in formatters() {
+        let dispatch = dispatch(formatter);
+
+        let allocations = allocations_per_event(&dispatch);
+        println!(
+            "format_event/{}: {} allocations per event",
+            name, allocations
+        );
+        assert_eq!(allocations, 0.0, "formatting an event allocated");
+
+        group.bench_function(name, |b| {
+            tracing::dispatcher::with_default(&dispatch, || {
+                let mut iteration = 0;
+                b.iter(|| {
+                    iteration += 1;
+                    log_event(black_box(iteration));
+                });
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_format_event);
+criterion_main!(benches);
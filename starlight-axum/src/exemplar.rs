@@ -0,0 +1,391 @@
+use opentelemetry::KeyValue;
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::metrics::Temporality;
+use opentelemetry_sdk::metrics::data::{Aggregation, Exemplar, Histogram, ResourceMetrics};
+use opentelemetry_sdk::metrics::exporter::PushMetricExporter;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Most recent measurements kept per series; enough to cover a spread of buckets.
+const RESERVOIR_SIZE: usize = 16;
+/// Series tracked at once; the one recorded to least recently makes room for a new one.
+const MAX_SERIES: usize = 2048;
+/// How long exemplars are kept unless a reader collects less often, see [`retain_exemplars_for`].
+/// Matches the usual Prometheus scrape interval.
+pub(crate) const DEFAULT_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// A histogram measurement that was recorded inside a sampled trace.
+#[derive(Debug, Clone, Copy)]
+pub struct SampledExemplar {
+    pub value: f64,
+    pub time: SystemTime,
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+}
+
+type SeriesKey = (String, Vec<(String, String)>);
+
+struct Reservoirs {
+    series: HashMap<SeriesKey, VecDeque<SampledExemplar>>,
+    /// Exemplars older than this are dropped.
+    max_age: Duration,
+}
+
+impl Reservoirs {
+    fn new() -> Self {
+        Reservoirs {
+            series: HashMap::new(),
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+
+    fn is_expired(&self, exemplar: &SampledExemplar, now: SystemTime) -> bool {
+        now.duration_since(exemplar.time).unwrap_or_default() > self.max_age
+    }
+
+    fn record(&mut self, key: SeriesKey, exemplar: SampledExemplar) {
+        if !self.series.contains_key(&key) && self.series.len() >= MAX_SERIES {
+            self.make_room(exemplar.time);
+        }
+        let reservoir = self.series.entry(key).or_default();
+        if reservoir.len() == RESERVOIR_SIZE {
+            reservoir.pop_front();
+        }
+        reservoir.push_back(exemplar);
+    }
+
+    /// Drops expired series, then the least recently recorded one if that is not enough.
+    fn make_room(&mut self, now: SystemTime) {
+        let expired = self
+            .series
+            .iter()
+            .filter(|(_, reservoir)| {
+                reservoir
+                    .back()
+                    .is_none_or(|latest| self.is_expired(latest, now))
+            })
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in expired {
+            self.series.remove(&key);
+        }
+        if self.series.len() < MAX_SERIES {
+            return;
+        }
+        let least_recent = self
+            .series
+            .iter()
+            .min_by_key(|(_, reservoir)| reservoir.back().map(|latest| latest.time))
+            .map(|(key, _)| key.clone());
+        if let Some(key) = least_recent {
+            self.series.remove(&key);
+        }
+    }
+
+    /// Exemplars of the series in `start_time..=time`, dropping the expired ones on the way.
+    fn recent(
+        &mut self,
+        key: &SeriesKey,
+        start_time: SystemTime,
+        time: SystemTime,
+        now: SystemTime,
+    ) -> Vec<SampledExemplar> {
+        let Some(reservoir) = self.series.get(key) else {
+            return vec![];
+        };
+        // Exemplars are kept in recording order, so the expired ones are at the front.
+        let expired = reservoir
+            .iter()
+            .take_while(|exemplar| self.is_expired(exemplar, now))
+            .count();
+        let reservoir = self.series.get_mut(key).expect("looked up above");
+        reservoir.drain(..expired);
+        let recent = reservoir
+            .iter()
+            .filter(|exemplar| exemplar.time >= start_time && exemplar.time <= time)
+            .copied()
+            .collect();
+        if reservoir.is_empty() {
+            self.series.remove(key);
+        }
+        recent
+    }
+}
+
+// The SDK does not sample exemplars yet, so histogram recordings feed this reservoir instead
+// and the exporters attach its content to the matching data points.
+static EXEMPLARS: LazyLock<Mutex<Reservoirs>> = LazyLock::new(|| Mutex::new(Reservoirs::new()));
+
+/// Keeps exemplars for at least `interval`, so a reader collecting that often still sees them.
+pub fn retain_exemplars_for(interval: Duration) {
+    let mut exemplars = EXEMPLARS.lock().unwrap_or_else(PoisonError::into_inner);
+    exemplars.max_age = exemplars.max_age.max(interval);
+}
+
+fn series_key(metric: &str, attributes: &[KeyValue]) -> SeriesKey {
+    let mut attributes = attributes
+        .iter()
+        .map(|kv| (kv.key.to_string(), kv.value.to_string()))
+        .collect::<Vec<_>>();
+    attributes.sort();
+    attributes.dedup_by(|a, b| a.0 == b.0);
+    (metric.to_owned(), attributes)
+}
+
+/// Remembers `value` as an exemplar of the series when the current span belongs to a sampled trace.
+pub fn record_exemplar(metric: &str, value: f64, attributes: &[KeyValue]) {
+    let context = tracing::Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    if !span_context.is_valid() || !span_context.is_sampled() {
+        return;
+    }
+
+    let exemplar = SampledExemplar {
+        value,
+        time: SystemTime::now(),
+        trace_id: span_context.trace_id().to_bytes(),
+        span_id: span_context.span_id().to_bytes(),
+    };
+    EXEMPLARS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .record(series_key(metric, attributes), exemplar);
+}
+
+/// Exemplars of the series recorded between `start_time` and `time`, and at most `interval`
+/// before `time`, so that each collection only gets the ones of its own interval.
+pub fn exemplars_for(
+    metric: &str,
+    attributes: &[KeyValue],
+    start_time: SystemTime,
+    time: SystemTime,
+    interval: Duration,
+) -> Vec<SampledExemplar> {
+    let start_time = time
+        .checked_sub(interval)
+        .map_or(start_time, |oldest| oldest.max(start_time));
+    EXEMPLARS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .recent(
+            &series_key(metric, attributes),
+            start_time,
+            time,
+            SystemTime::now(),
+        )
+}
+
+/// Picks the latest exemplar for each bucket delimited by `bounds`, with a trailing `+Inf` bucket.
+pub fn bucket_exemplars(
+    exemplars: &[SampledExemplar],
+    bounds: &[f64],
+) -> Vec<Option<SampledExemplar>> {
+    let mut buckets = vec![None; bounds.len() + 1];
+    for exemplar in exemplars {
+        let bucket = bounds
+            .iter()
+            .position(|bound| exemplar.value <= *bound)
+            .unwrap_or(bounds.len());
+        buckets[bucket] = Some(*exemplar);
+    }
+    buckets
+}
+
+/// Decorates a push exporter so exported `f64` histogram points carry their exemplars.
+#[derive(Debug)]
+pub struct ExemplarExporter<E> {
+    exporter: E,
+    interval: Duration,
+}
+
+impl<E> ExemplarExporter<E> {
+    pub fn new(exporter: E) -> Self {
+        ExemplarExporter {
+            exporter,
+            interval: DEFAULT_MAX_AGE,
+        }
+    }
+
+    /// Export interval of the reader driving the exporter; only exemplars recorded within the
+    /// last interval are attached.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        retain_exemplars_for(interval);
+        self.interval = interval;
+        self
+    }
+}
+
+impl<E: PushMetricExporter> PushMetricExporter for ExemplarExporter<E> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> OTelSdkResult {
+        attach_exemplars(metrics, self.interval);
+        self.exporter.export(metrics).await
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.exporter.force_flush()
+    }
+
+    fn shutdown(&self) -> OTelSdkResult {
+        self.exporter.shutdown()
+    }
+
+    fn temporality(&self) -> Temporality {
+        self.exporter.temporality()
+    }
+}
+
+fn attach_exemplars(metrics: &mut ResourceMetrics, interval: Duration) {
+    for scope_metrics in &mut metrics.scope_metrics {
+        for metric in &mut scope_metrics.metrics {
+            let data = Aggregation::as_mut(&mut *metric.data);
+            let Some(histogram) = data.downcast_mut::<Histogram<f64>>() else {
+                continue;
+            };
+            let (start_time, time) = (histogram.start_time, histogram.time);
+            for data_point in &mut histogram.data_points {
+                let exemplars = exemplars_for(
+                    &metric.name,
+                    &data_point.attributes,
+                    start_time,
+                    time,
+                    interval,
+                );
+                data_point.exemplars = bucket_exemplars(&exemplars, &data_point.bounds)
+                    .into_iter()
+                    .flatten()
+                    .map(|exemplar| Exemplar {
+                        filtered_attributes: vec![],
+                        time: exemplar.time,
+                        value: exemplar.value,
+                        span_id: exemplar.span_id,
+                        trace_id: exemplar.trace_id,
+                    })
+                    .collect();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exemplar(value: f64, time: SystemTime) -> SampledExemplar {
+        SampledExemplar {
+            value,
+            time,
+            trace_id: [1; 16],
+            span_id: [1; 8],
+        }
+    }
+
+    fn key(series: usize) -> SeriesKey {
+        series_key("latency", &[KeyValue::new("series", series as i64)])
+    }
+
+    fn values(exemplars: &[SampledExemplar]) -> Vec<f64> {
+        exemplars.iter().map(|exemplar| exemplar.value).collect()
+    }
+
+    #[test]
+    fn keys_series_by_sorted_attributes() {
+        let forward = [KeyValue::new("a", 1), KeyValue::new("b", 2)];
+        let backward = [KeyValue::new("b", 2), KeyValue::new("a", 1)];
+        assert_eq!(series_key("m", &forward), series_key("m", &backward));
+        assert_ne!(series_key("m", &forward), series_key("n", &forward));
+    }
+
+    #[test]
+    fn keeps_the_latest_exemplars_per_series() {
+        let now = SystemTime::now();
+        let mut reservoirs = Reservoirs::new();
+        for value in 0..RESERVOIR_SIZE + 2 {
+            reservoirs.record(key(0), exemplar(value as f64, now));
+        }
+        let recent = reservoirs.recent(&key(0), now, now, now);
+        assert_eq!(recent.len(), RESERVOIR_SIZE);
+        assert_eq!(recent[0].value, 2.0);
+    }
+
+    #[test]
+    fn drops_expired_exemplars_and_empty_series() {
+        let now = SystemTime::now();
+        let old = now - DEFAULT_MAX_AGE * 2;
+        let mut reservoirs = Reservoirs::new();
+        reservoirs.record(key(0), exemplar(1.0, old));
+        reservoirs.record(key(0), exemplar(2.0, now));
+        reservoirs.record(key(1), exemplar(3.0, old));
+
+        assert_eq!(values(&reservoirs.recent(&key(0), old, now, now)), [2.0]);
+        assert!(reservoirs.recent(&key(1), old, now, now).is_empty());
+        assert!(!reservoirs.series.contains_key(&key(1)));
+    }
+
+    #[test]
+    fn filters_by_collection_window() {
+        let now = SystemTime::now();
+        let mut reservoirs = Reservoirs::new();
+        for seconds in [30, 20, 10] {
+            let time = now - Duration::from_secs(seconds);
+            reservoirs.record(key(0), exemplar(seconds as f64, time));
+        }
+        let start_time = now - Duration::from_secs(25);
+        let time = now - Duration::from_secs(5);
+        assert_eq!(
+            values(&reservoirs.recent(&key(0), start_time, time, now)),
+            [20.0, 10.0]
+        );
+    }
+
+    #[test]
+    fn caps_the_number_of_series() {
+        let now = SystemTime::now();
+        let mut reservoirs = Reservoirs::new();
+        for series in 0..MAX_SERIES {
+            let time = now - Duration::from_millis((MAX_SERIES - series) as u64);
+            reservoirs.record(key(series), exemplar(1.0, time));
+        }
+        reservoirs.record(key(0), exemplar(2.0, now));
+        reservoirs.record(key(MAX_SERIES), exemplar(1.0, now));
+
+        assert_eq!(reservoirs.series.len(), MAX_SERIES);
+        // Series 1 was recorded to least recently, now that series 0 got a new exemplar.
+        assert!(!reservoirs.series.contains_key(&key(1)));
+        assert!(reservoirs.series.contains_key(&key(0)));
+        assert!(reservoirs.series.contains_key(&key(MAX_SERIES)));
+    }
+
+    #[test]
+    fn makes_room_by_dropping_expired_series_first() {
+        let now = SystemTime::now();
+        let mut reservoirs = Reservoirs::new();
+        reservoirs.record(key(0), exemplar(1.0, now - DEFAULT_MAX_AGE * 2));
+        for series in 1..MAX_SERIES {
+            reservoirs.record(key(series), exemplar(1.0, now));
+        }
+        reservoirs.record(key(MAX_SERIES), exemplar(1.0, now));
+        assert_eq!(reservoirs.series.len(), MAX_SERIES);
+        assert!(!reservoirs.series.contains_key(&key(0)));
+    }
+
+    #[test]
+    fn assigns_exemplars_to_buckets() {
+        let now = SystemTime::now();
+        let exemplars = [
+            exemplar(0.05, now),
+            exemplar(0.5, now),
+            exemplar(0.7, now),
+            exemplar(5.0, now),
+        ];
+        let buckets = bucket_exemplars(&exemplars, &[0.1, 1.0]);
+        let values = buckets
+            .iter()
+            .map(|exemplar| exemplar.map(|exemplar| exemplar.value))
+            .collect::<Vec<_>>();
+        assert_eq!(values, [Some(0.05), Some(0.7), Some(5.0)]);
+    }
+}
@@ -0,0 +1,163 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Serialize;
+use starlight_tokio::{HealthRegistry, HealthSnapshot, HealthStatus};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Serialize)]
+struct HealthBody {
+    status: &'static str,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    shutting_down: bool,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    checks: BTreeMap<String, CheckBody>,
+}
+
+#[derive(Debug, Serialize)]
+struct CheckBody {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<String>,
+}
+
+impl From<HealthSnapshot> for HealthBody {
+    fn from(snapshot: HealthSnapshot) -> Self {
+        HealthBody {
+            status: snapshot.status.as_str(),
+            shutting_down: snapshot.shutting_down,
+            checks: snapshot
+                .checks
+                .into_iter()
+                .map(|(name, health)| {
+                    let check = CheckBody {
+                        status: health.status.as_str(),
+                        details: health.details,
+                    };
+                    (name, check)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Kubernetes probe endpoints backed by `health`:
+///
+/// - `/livez` answers 200 whenever the process can serve requests at all; it runs no checks, so
+///   a failing dependency does not get the pod restarted.
+/// - `/readyz` answers 503 once shutdown has started or any check is unhealthy.
+/// - `/healthz` answers 503 if any check is unhealthy, with every check's status and details.
+pub fn health_router(health: HealthRegistry) -> Router {
+    Router::new()
+        .route("/livez", get(livez_handler))
+        .route("/readyz", get(readyz_handler))
+        .route("/healthz", get(healthz_handler))
+        .with_state(health)
+}
+
+async fn livez_handler() -> Response {
+    let body = HealthBody {
+        status: HealthStatus::Healthy.as_str(),
+        shutting_down: false,
+        checks: BTreeMap::new(),
+    };
+    (StatusCode::OK, Json(body)).into_response()
+}
+
+async fn readyz_handler(State(health): State<HealthRegistry>) -> Response {
+    let snapshot = health.check().await;
+    let status = if snapshot.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(HealthBody::from(snapshot))).into_response()
+}
+
+async fn healthz_handler(State(health): State<HealthRegistry>) -> Response {
+    let snapshot = health.check().await;
+    let status = if snapshot.status == HealthStatus::Unhealthy {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    (status, Json(HealthBody::from(snapshot))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use http_body_util::BodyExt;
+    use starlight_tokio::Health;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    async fn get(health: &HealthRegistry, path: &str) -> (StatusCode, serde_json::Value) {
+        let request = Request::get(path).body(Body::empty()).unwrap();
+        let response = health_router(health.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn healthz_lists_every_check_and_fails_when_one_is_unhealthy() {
+        let health = HealthRegistry::new();
+        health.register("db", || async { Health::healthy() });
+        health.register("cache", || async { Health::degraded("slow") });
+
+        let (status, body) = get(&health, "/healthz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "status": "degraded",
+                "checks": {
+                    "cache": {"status": "degraded", "details": "slow"},
+                    "db": {"status": "healthy"},
+                },
+            })
+        );
+
+        health.register("queue", || async { Health::unhealthy("down") });
+        let (status, body) = get(&health, "/healthz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unhealthy");
+        assert_eq!(body["checks"]["queue"]["details"], "down");
+    }
+
+    #[tokio::test]
+    async fn readyz_fails_while_shutting_down_and_livez_does_not() {
+        let health = HealthRegistry::new();
+        health.register("db", || async { Health::healthy() });
+        assert_eq!(get(&health, "/readyz").await.0, StatusCode::OK);
+
+        health.mark_shutting_down();
+        let (status, body) = get(&health, "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["shutting_down"], true);
+        assert_eq!(get(&health, "/livez").await.0, StatusCode::OK);
+        assert_eq!(get(&health, "/healthz").await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn checks_that_time_out_fail_readiness() {
+        let health = HealthRegistry::with_check_timeout(Duration::from_millis(20));
+        health.register("slow", || async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Health::healthy()
+        });
+
+        let (status, body) = get(&health, "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["checks"]["slow"]["details"], "no answer within 20ms");
+        assert_eq!(get(&health, "/livez").await.0, StatusCode::OK);
+    }
+}
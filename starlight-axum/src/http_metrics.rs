@@ -0,0 +1,510 @@
+use crate::exemplar::record_exemplar;
+use crate::meter::Metric;
+use axum::extract::MatchedPath;
+use axum::http::{Method, Request, Response, Version};
+use http_body::{Body, Frame, SizeHint};
+use opentelemetry::KeyValue;
+use opentelemetry::metrics::{Histogram, Meter, UpDownCounter};
+use opentelemetry_semantic_conventions::attribute::{
+    ERROR_TYPE, HTTP_REQUEST_METHOD, HTTP_RESPONSE_STATUS_CODE, HTTP_ROUTE,
+    NETWORK_PROTOCOL_VERSION, URL_SCHEME,
+};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tower::{Layer, Service};
+
+/// Not part of the semantic conventions; lets dashboards group by `2xx`/`4xx`/`5xx` cheaply.
+pub const HTTP_RESPONSE_STATUS_CLASS: &str = "http.response.status_class";
+
+/// Bucket boundaries advised by the `http.server.request.duration` semantic convention.
+const DURATION_BOUNDARIES: [f64; 14] = [
+    0.005, 0.01, 0.025, 0.05, 0.075, 0.1, 0.25, 0.5, 0.75, 1.0, 2.5, 5.0, 7.5, 10.0,
+];
+
+struct HttpServerInstruments {
+    request_duration: Histogram<f64>,
+    active_requests: UpDownCounter<i64>,
+    request_body_size: Histogram<u64>,
+    response_body_size: Histogram<u64>,
+}
+
+/// Records the `http.server.*` metrics from the starlight metric catalog.
+#[derive(Clone)]
+pub struct HttpMetricsLayer {
+    instruments: Arc<HttpServerInstruments>,
+    scheme: Scheme,
+}
+
+/// How `url.scheme` is found for requests in origin form, which is nearly all of them.
+#[derive(Debug, Clone, Copy)]
+struct Scheme {
+    default: &'static str,
+    trust_forwarded_proto: bool,
+}
+
+impl HttpMetricsLayer {
+    pub fn new(meter: &Meter) -> Self {
+        let request_duration = Metric::HttpServerRequestDuration;
+        let active_requests = Metric::HttpServerActiveRequests;
+        let request_body_size = Metric::HttpServerRequestBodySize;
+        let response_body_size = Metric::HttpServerResponseBodySize;
+
+        HttpMetricsLayer {
+            instruments: Arc::new(HttpServerInstruments {
+                request_duration: meter
+                    .f64_histogram(request_duration.name())
+                    .with_description(request_duration.description())
+                    .with_unit(request_duration.unit())
+                    .with_boundaries(DURATION_BOUNDARIES.to_vec())
+                    .build(),
+                active_requests: meter
+                    .i64_up_down_counter(active_requests.name())
+                    .with_description(active_requests.description())
+                    .with_unit(active_requests.unit())
+                    .build(),
+                request_body_size: meter
+                    .u64_histogram(request_body_size.name())
+                    .with_description(request_body_size.description())
+                    .with_unit(request_body_size.unit())
+                    .build(),
+                response_body_size: meter
+                    .u64_histogram(response_body_size.name())
+                    .with_description(response_body_size.description())
+                    .with_unit(response_body_size.unit())
+                    .build(),
+            }),
+            scheme: Scheme {
+                default: "http",
+                trust_forwarded_proto: false,
+            },
+        }
+    }
+
+    /// `url.scheme` of requests that don't carry one, `http` by default; e.g. `https` when the
+    /// service terminates TLS itself.
+    pub fn with_scheme(mut self, scheme: &'static str) -> Self {
+        self.scheme.default = scheme;
+        self
+    }
+
+    /// Takes `url.scheme` from `X-Forwarded-Proto`, for services behind a proxy that sets it.
+    /// Values other than `http` and `https` are ignored.
+    pub fn with_forwarded_proto(mut self, trusted: bool) -> Self {
+        self.scheme.trust_forwarded_proto = trusted;
+        self
+    }
+}
+
+impl<S> Layer<S> for HttpMetricsLayer {
+    type Service = HttpMetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HttpMetricsService {
+            inner,
+            instruments: self.instruments.clone(),
+            scheme: self.scheme,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HttpMetricsService<S> {
+    inner: S,
+    instruments: Arc<HttpServerInstruments>,
+    scheme: Scheme,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for HttpMetricsService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Body,
+    ResBody: Body,
+{
+    type Response = Response<MeteredBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let start = Instant::now();
+        let instruments = self.instruments.clone();
+
+        let active_attributes = vec![
+            KeyValue::new(HTTP_REQUEST_METHOD, method_attribute(req.method())),
+            KeyValue::new(URL_SCHEME, scheme_attribute(&req, self.scheme)),
+        ];
+        let mut attributes = active_attributes.clone();
+        attributes.push(KeyValue::new(
+            NETWORK_PROTOCOL_VERSION,
+            protocol_version_attribute(req.version()),
+        ));
+        if let Some(route) = req.extensions().get::<MatchedPath>() {
+            attributes.push(KeyValue::new(HTTP_ROUTE, route.as_str().to_owned()));
+        }
+        let request_body_size = body_size(req.body().size_hint(), req.headers());
+
+        let active_request = ActiveRequest::start(&instruments, active_attributes);
+        let future = self.inner.call(req);
+
+        Box::pin(async move {
+            let result = future.await;
+            drop(active_request);
+
+            match result {
+                Ok(response) => {
+                    let status = response.status();
+                    attributes.push(KeyValue::new(
+                        HTTP_RESPONSE_STATUS_CODE,
+                        i64::from(status.as_u16()),
+                    ));
+                    attributes.push(KeyValue::new(
+                        HTTP_RESPONSE_STATUS_CLASS,
+                        format!("{}xx", status.as_u16() / 100),
+                    ));
+                    if status.is_server_error() {
+                        attributes.push(KeyValue::new(ERROR_TYPE, status.as_u16().to_string()));
+                    }
+
+                    if let Some(size) = request_body_size {
+                        instruments.request_body_size.record(size, &attributes);
+                    }
+
+                    let (parts, body) = response.into_parts();
+                    let body = MeteredBody {
+                        inner: Box::pin(body),
+                        bytes: 0,
+                        start: Some(start),
+                        instruments,
+                        attributes,
+                    };
+                    Ok(Response::from_parts(parts, body))
+                }
+                Err(err) => {
+                    attributes.push(KeyValue::new(ERROR_TYPE, "_OTHER"));
+                    let duration = start.elapsed().as_secs_f64();
+                    instruments.request_duration.record(duration, &attributes);
+                    record_exemplar(Metric::HttpServerRequestDuration.name(), duration, &attributes);
+                    Err(err)
+                }
+            }
+        })
+    }
+}
+
+/// Counts a request in `http.server.active_requests` until dropped, so that requests whose
+/// future is dropped early, e.g. when the client disconnects or a timeout fires, are uncounted
+/// as well.
+struct ActiveRequest {
+    instruments: Arc<HttpServerInstruments>,
+    attributes: Vec<KeyValue>,
+}
+
+impl ActiveRequest {
+    fn start(instruments: &Arc<HttpServerInstruments>, attributes: Vec<KeyValue>) -> Self {
+        instruments.active_requests.add(1, &attributes);
+        ActiveRequest {
+            instruments: instruments.clone(),
+            attributes,
+        }
+    }
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        self.instruments.active_requests.add(-1, &self.attributes);
+    }
+}
+
+/// Response body that counts the bytes actually sent. The request duration and response size
+/// are recorded once the body ends, or is dropped, so streamed responses are timed in full.
+pub struct MeteredBody<B> {
+    inner: Pin<Box<B>>,
+    bytes: u64,
+    /// Taken once the request is recorded.
+    start: Option<Instant>,
+    instruments: Arc<HttpServerInstruments>,
+    attributes: Vec<KeyValue>,
+}
+
+impl<B> MeteredBody<B> {
+    fn finish(&mut self) {
+        let Some(start) = self.start.take() else {
+            return;
+        };
+        let duration = start.elapsed().as_secs_f64();
+        self.instruments
+            .request_duration
+            .record(duration, &self.attributes);
+        record_exemplar(Metric::HttpServerRequestDuration.name(), duration, &self.attributes);
+        self.instruments
+            .response_body_size
+            .record(self.bytes, &self.attributes);
+    }
+}
+
+impl<B: Body> Body for MeteredBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        let polled = this.inner.as_mut().poll_frame(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    this.bytes += bytes::Buf::remaining(data) as u64;
+                }
+                if this.inner.is_end_stream() {
+                    this.finish();
+                }
+            }
+            Poll::Ready(None | Some(Err(_))) => this.finish(),
+            Poll::Pending => {}
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B> Drop for MeteredBody<B> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+fn method_attribute(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::PATCH => "PATCH",
+        Method::HEAD => "HEAD",
+        Method::OPTIONS => "OPTIONS",
+        Method::CONNECT => "CONNECT",
+        Method::TRACE => "TRACE",
+        _ => "_OTHER",
+    }
+}
+
+fn scheme_attribute<B>(req: &Request<B>, scheme: Scheme) -> &'static str {
+    let known = |value: &str| {
+        let value = value.trim();
+        if value.eq_ignore_ascii_case("https") {
+            Some("https")
+        } else if value.eq_ignore_ascii_case("http") {
+            Some("http")
+        } else {
+            None
+        }
+    };
+    req.uri()
+        .scheme_str()
+        .and_then(known)
+        .or_else(|| {
+            if !scheme.trust_forwarded_proto {
+                return None;
+            }
+            // A proxy chain appends to the header; the first value is the client's.
+            let forwarded = req.headers().get("x-forwarded-proto")?.to_str().ok()?;
+            known(forwarded.split(',').next()?)
+        })
+        .unwrap_or(scheme.default)
+}
+
+fn protocol_version_attribute(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_11 => "1.1",
+        Version::HTTP_2 => "2",
+        Version::HTTP_3 => "3",
+        _ => "_OTHER",
+    }
+}
+
+fn body_size(size_hint: SizeHint, headers: &axum::http::HeaderMap) -> Option<u64> {
+    size_hint.exact().or_else(|| {
+        headers
+            .get(axum::http::header::CONTENT_LENGTH)?
+            .to_str()
+            .ok()?
+            .parse()
+            .ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prometheus::{ExpositionFormat, PrometheusExporter};
+    use axum::Router;
+    use axum::body::Body as AxumBody;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use http_body_util::BodyExt;
+    use opentelemetry::metrics::MeterProvider;
+    use opentelemetry_sdk::metrics::SdkMeterProvider;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    /// A router metered on a provider of its own, which has to outlive the scrape.
+    fn metered(router: Router) -> (Router, PrometheusExporter, SdkMeterProvider) {
+        let exporter = PrometheusExporter::new();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(exporter.clone())
+            .build();
+        let router = router.layer(HttpMetricsLayer::new(&provider.meter("test")));
+        (router, exporter, provider)
+    }
+
+    fn scrape(exporter: &PrometheusExporter) -> String {
+        exporter.encode(ExpositionFormat::Prometheus).unwrap()
+    }
+
+    /// Value of the `name` sample whose labels, in any order, are the scope and `labels`.
+    fn sample<'t>(text: &'t str, name: &str, labels: &[&str]) -> Option<&'t str> {
+        let mut expected = labels.to_vec();
+        expected.push("otel_scope_name=\"test\"");
+        expected.sort();
+        text.lines().find_map(|line| {
+            let (line_labels, value) = line
+                .strip_prefix(name)?
+                .strip_prefix('{')?
+                .rsplit_once("} ")?;
+            let mut line_labels = line_labels.split(',').collect::<Vec<_>>();
+            line_labels.sort();
+            (line_labels == expected).then_some(value)
+        })
+    }
+
+    fn get_request(uri: &str) -> Request<AxumBody> {
+        Request::get(uri).body(AxumBody::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn records_route_method_status_and_response_size() {
+        let router = Router::new().route(
+            "/users/{id}",
+            get(|| async { (StatusCode::CREATED, "hello") }),
+        );
+        let (router, exporter, _provider) = metered(router);
+
+        let response = router.oneshot(get_request("/users/42")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        response.into_body().collect().await.unwrap();
+
+        let text = scrape(&exporter);
+        let labels = [
+            "http_request_method=\"GET\"",
+            "http_response_status_class=\"2xx\"",
+            "http_response_status_code=\"201\"",
+            "http_route=\"/users/{id}\"",
+            "network_protocol_version=\"1.1\"",
+            "url_scheme=\"http\"",
+        ];
+        assert_eq!(
+            sample(&text, "http_server_request_duration_seconds_count", &labels),
+            Some("1"),
+            "{}",
+            text
+        );
+        assert_eq!(
+            sample(&text, "http_server_response_body_size_bytes_sum", &labels),
+            Some("5"),
+            "{}",
+            text
+        );
+    }
+
+    #[tokio::test]
+    async fn records_duration_once_the_body_ends() {
+        let router = Router::new().route("/", get(|| async { "streamed" }));
+        let (router, exporter, _provider) = metered(router);
+
+        let response = router.oneshot(get_request("/")).await.unwrap();
+        assert!(!scrape(&exporter).contains("http_server_request_duration_seconds_count"));
+
+        let mut body = response.into_body();
+        while body.frame().await.is_some() {}
+        assert!(scrape(&exporter).contains("http_server_request_duration_seconds_count{"));
+    }
+
+    #[tokio::test]
+    async fn uncounts_active_requests_that_are_cancelled() {
+        let router = Router::new().route("/", get(std::future::pending::<&str>));
+        let (router, exporter, _provider) = metered(router);
+
+        let mut call = Box::pin(router.oneshot(get_request("/")));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), &mut call)
+                .await
+                .is_err()
+        );
+        let active = "http_server_active_requests";
+        let labels = ["http_request_method=\"GET\"", "url_scheme=\"http\""];
+        assert_eq!(sample(&scrape(&exporter), active, &labels), Some("1"));
+
+        drop(call);
+        let text = scrape(&exporter);
+        assert_eq!(sample(&text, active, &labels), Some("0"), "{}", text);
+        assert!(!text.contains("http_server_request_duration_seconds_count"));
+    }
+
+    #[test]
+    fn takes_the_scheme_from_the_uri_layer_or_trusted_proxy() {
+        let layer_scheme = |default: &'static str, trust_forwarded_proto: bool| Scheme {
+            default,
+            trust_forwarded_proto,
+        };
+        let forwarded = |value: &str| {
+            Request::get("/")
+                .header("x-forwarded-proto", value)
+                .body(())
+                .unwrap()
+        };
+        let absolute = Request::get("https://example.com/").body(()).unwrap();
+
+        for (req, scheme, expected) in [
+            (&absolute, layer_scheme("http", false), "https"),
+            (&forwarded("https"), layer_scheme("http", false), "http"),
+            (&forwarded("https"), layer_scheme("https", false), "https"),
+            (
+                &forwarded("HTTPS, http"),
+                layer_scheme("http", true),
+                "https",
+            ),
+            (&forwarded("ftp"), layer_scheme("http", true), "http"),
+            (
+                &Request::get("/").body(()).unwrap(),
+                layer_scheme("https", true),
+                "https",
+            ),
+        ] {
+            assert_eq!(
+                scheme_attribute(req, scheme),
+                expected,
+                "{:?} with {:?}",
+                req.headers(),
+                scheme
+            );
+        }
+    }
+}
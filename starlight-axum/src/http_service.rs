@@ -0,0 +1,292 @@
+use crate::middleware::{generate_request_id_middleware, oltp_middleware, trace_middleware};
+use async_trait::async_trait;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use starlight_tokio::{ServiceMetadata, ServiceResult, ShutdownToken, StarlightService};
+use std::net::SocketAddr;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tower::{Layer, ServiceExt};
+use tower_http::normalize_path::NormalizePathLayer;
+
+/// Serves an axum `Router` as a `StarlightService`, wrapped in the starlight middleware stack:
+/// request ids, tracing, HTTP metrics and trailing-slash trimming. Handlers can extract the
+/// peer address with `ConnectInfo<SocketAddr>`.
+///
+/// The listener is bound in `start`, so bind errors fail the service before it reports ready.
+/// Once shutdown drains the service it stops accepting connections and waits for open ones to
+/// finish, for at most the drain timeout or until shutdown is cancelled. Connections still open
+/// then are aborted.
+pub struct HttpService {
+    name: String,
+    router: Router,
+    addr: SocketAddr,
+    drain_timeout: Duration,
+    listener: Mutex<Option<TcpListener>>,
+    bound_tx: watch::Sender<Option<SocketAddr>>,
+}
+
+impl HttpService {
+    pub fn new(name: impl Into<String>, router: Router, addr: impl Into<SocketAddr>) -> Self {
+        let (bound_tx, _) = watch::channel(None);
+        HttpService {
+            name: name.into(),
+            router,
+            addr: addr.into(),
+            drain_timeout: Duration::from_secs(30),
+            listener: Mutex::new(None),
+            bound_tx,
+        }
+    }
+
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// Handle to the address actually bound, e.g. to find the port when binding port 0.
+    pub fn bound_addr(&self) -> BoundAddr {
+        BoundAddr(self.bound_tx.subscribe())
+    }
+
+    fn app(&self) -> Router {
+        self.router
+            .clone()
+            .layer(oltp_middleware())
+            .layer(trace_middleware())
+            .layer(generate_request_id_middleware())
+    }
+}
+
+#[async_trait]
+impl StarlightService for HttpService {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn metadata(&self) -> ServiceMetadata {
+        ServiceMetadata {
+            description: Some(format!("HTTP server on {}", self.addr)),
+            ..ServiceMetadata::default()
+        }
+    }
+
+    async fn start(&self) -> ServiceResult {
+        let listener = TcpListener::bind(self.addr).await?;
+        let local_addr = listener.local_addr()?;
+        info!("{} listening on {}", self.name, local_addr);
+        *self.listener.lock().unwrap_or_else(PoisonError::into_inner) = Some(listener);
+        self.bound_tx.send_replace(Some(local_addr));
+        Ok(())
+    }
+
+    async fn run(&self, shutdown: ShutdownToken) -> ServiceResult {
+        let listener = self
+            .listener
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .ok_or("HttpService::run called before start")?;
+        let app = NormalizePathLayer::trim_trailing_slash().layer(self.app());
+
+        let builder = auto::Builder::new(TokioExecutor::new());
+        let graceful = GracefulShutdown::new();
+        // Connections run as tasks of their own, which is what lets the deadline abort them.
+        let mut connections = JoinSet::new();
+
+        loop {
+            let (stream, remote_addr) = tokio::select! {
+                _ = shutdown.draining() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        // Mostly running out of file descriptors; retrying right away would spin.
+                        warn!("{} failed to accept a connection: {}", self.name, err);
+                        tokio::select! {
+                            _ = shutdown.draining() => break,
+                            _ = tokio::time::sleep(Duration::from_secs(1)) => continue,
+                        }
+                    }
+                },
+            };
+            while connections.try_join_next().is_some() {}
+
+            let service = TowerToHyperService::new(app.clone().map_request(
+                move |mut req: hyper::Request<Incoming>| {
+                    req.extensions_mut().insert(ConnectInfo(remote_addr));
+                    req.map(Body::new)
+                },
+            ));
+            let connection = builder
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .into_owned();
+            let connection = graceful.watch(connection);
+            connections.spawn(async move {
+                if let Err(err) = connection.await {
+                    trace!("failed to serve connection from {}: {}", remote_addr, err);
+                }
+            });
+        }
+        drop(listener);
+
+        let deadline = async {
+            tokio::select! {
+                _ = tokio::time::sleep(self.drain_timeout) => {}
+                _ = shutdown.cancelled() => {}
+            }
+        };
+        tokio::select! {
+            _ = graceful.shutdown() => {}
+            _ = deadline => {
+                warn!(
+                    "{} still had {} open connections when draining ended, aborting them",
+                    self.name,
+                    connections.len()
+                );
+                connections.abort_all();
+            }
+        }
+        while connections.join_next().await.is_some() {}
+        info!("{} stopped serving", self.name);
+        Ok(())
+    }
+}
+
+/// Address an `HttpService` is listening on, known once the service has started.
+#[derive(Debug, Clone)]
+pub struct BoundAddr(watch::Receiver<Option<SocketAddr>>);
+
+impl BoundAddr {
+    pub fn get(&self) -> Option<SocketAddr> {
+        *self.0.borrow()
+    }
+
+    /// Waits for the service to bind; `None` if it was dropped before it did.
+    pub async fn wait(&self) -> Option<SocketAddr> {
+        let mut bound_rx = self.0.clone();
+        let addr = bound_rx.wait_for(Option::is_some).await.ok()?;
+        *addr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use starlight_tokio::ShutdownReason;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::sync::Notify;
+    use tokio::task::JoinHandle;
+
+    async fn serving(
+        router: Router,
+        drain_timeout: Duration,
+    ) -> (SocketAddr, ShutdownToken, JoinHandle<ServiceResult>) {
+        let service = Arc::new(
+            HttpService::new("test", router, ([127, 0, 0, 1], 0)).with_drain_timeout(drain_timeout),
+        );
+        service.start().await.unwrap();
+        let addr = service.bound_addr().get().unwrap();
+        let shutdown = ShutdownToken::new();
+        let token = shutdown.clone();
+        let run = tokio::spawn(async move { service.run(token).await });
+        (addr, shutdown, run)
+    }
+
+    async fn send_get(addr: SocketAddr, path: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        stream
+    }
+
+    async fn read_response(mut stream: TcpStream) -> String {
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response).await;
+        response
+    }
+
+    #[tokio::test]
+    async fn serves_requests_with_the_peer_address() {
+        let router = Router::new().route(
+            "/peer",
+            get(|ConnectInfo(peer): ConnectInfo<SocketAddr>| async move { peer.to_string() }),
+        );
+        let (addr, shutdown, run) = serving(router, Duration::from_secs(5)).await;
+
+        let stream = send_get(addr, "/peer/").await;
+        let peer = stream.local_addr().unwrap();
+        let response = read_response(stream).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with(&peer.to_string()), "{}", response);
+
+        shutdown.drain(ShutdownReason::AdminRequest);
+        run.await.unwrap().unwrap();
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn drain_lets_open_requests_finish() {
+        let entered = Arc::new(Notify::new());
+        let router = Router::new().route(
+            "/slow",
+            get({
+                let entered = entered.clone();
+                move || async move {
+                    entered.notify_one();
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    "done"
+                }
+            }),
+        );
+        let (addr, shutdown, run) = serving(router, Duration::from_secs(5)).await;
+
+        let stream = send_get(addr, "/slow").await;
+        entered.notified().await;
+        shutdown.drain(ShutdownReason::AdminRequest);
+        let response = read_response(stream).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("done"), "{}", response);
+        run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn drain_timeout_aborts_open_requests() {
+        let entered = Arc::new(Notify::new());
+        let router = Router::new().route(
+            "/stuck",
+            get({
+                let entered = entered.clone();
+                move || async move {
+                    entered.notify_one();
+                    std::future::pending::<&str>().await
+                }
+            }),
+        );
+        let (addr, shutdown, run) = serving(router, Duration::from_millis(50)).await;
+
+        let stream = send_get(addr, "/stuck").await;
+        entered.notified().await;
+        shutdown.drain(ShutdownReason::AdminRequest);
+        tokio::time::timeout(Duration::from_secs(5), run)
+            .await
+            .expect("the drain timeout stops the service")
+            .unwrap()
+            .unwrap();
+        assert_eq!(read_response(stream).await, "");
+    }
+}
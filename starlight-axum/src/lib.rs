@@ -1,17 +1,23 @@
 pub mod logger;
+pub mod logfmt;
 pub mod meter;
+pub mod exemplar;
 pub mod tracer;
 pub mod resource;
+pub mod resource_detector;
 pub mod oltp;
 pub mod middleware;
+pub mod http_metrics;
+pub mod prometheus;
+pub mod http_service;
+pub mod health;
+pub mod config;
+pub mod log_level;
+pub mod panic;
 
 #[macro_use]
 extern crate tracing;
 
-pub(crate) fn get_env_or_panic(variable: &str) -> String {
-    std::env::var(variable).expect(format!("{} is not set", variable).as_str())
-}
-
 pub(crate) fn get_env_or_default(variable: &str, default: String) -> String {
     std::env::var(variable).unwrap_or(default)
 }
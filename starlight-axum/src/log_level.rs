@@ -0,0 +1,348 @@
+use async_trait::async_trait;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use starlight_tokio::{ServiceResult, ShutdownToken, StarlightService};
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::watch;
+use tracing_subscriber::filter::ParseError;
+use tracing_subscriber::{EnvFilter, Registry, reload};
+
+static LOG_LEVEL_HANDLE: OnceLock<LogLevelHandle> = OnceLock::new();
+/// Handle to the filter installed by `config_oltp`.
+pub fn get_log_level_handle() -> &'static LogLevelHandle {
+    LOG_LEVEL_HANDLE
+        .get()
+        .expect("Failed to get the log level handle, was config_oltp called?")
+}
+
+/// Builds the reloadable filter layer from `directives` and registers its handle globally.
+pub(crate) fn init_log_level(directives: String) -> reload::Layer<EnvFilter, Registry> {
+    let (layer, handle) = reload::Layer::new(EnvFilter::new(&directives));
+    let _ = LOG_LEVEL_HANDLE.set(LogLevelHandle::new(handle, directives));
+    layer
+}
+
+#[derive(Debug)]
+pub enum LogLevelError {
+    Invalid(ParseError),
+    Reload(reload::Error),
+}
+
+impl fmt::Display for LogLevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogLevelError::Invalid(err) => write!(f, "invalid filter directives: {}", err),
+            LogLevelError::Reload(err) => write!(f, "failed to reload log filter: {}", err),
+        }
+    }
+}
+
+impl Error for LogLevelError {}
+
+struct LogLevelState {
+    directives: String,
+    /// Bumped on every change, so that a pending revert only applies to the change it belongs to.
+    generation: u64,
+    revert_at: Option<Instant>,
+}
+
+/// Changes the active `EnvFilter` directives at runtime.
+#[derive(Clone)]
+pub struct LogLevelHandle {
+    handle: reload::Handle<EnvFilter, Registry>,
+    default_directives: Arc<str>,
+    state: Arc<Mutex<LogLevelState>>,
+}
+
+impl LogLevelHandle {
+    fn new(handle: reload::Handle<EnvFilter, Registry>, default_directives: String) -> Self {
+        LogLevelHandle {
+            handle,
+            state: Arc::new(Mutex::new(LogLevelState {
+                directives: default_directives.clone(),
+                generation: 0,
+                revert_at: None,
+            })),
+            default_directives: default_directives.into(),
+        }
+    }
+
+    pub fn directives(&self) -> String {
+        self.state().directives.clone()
+    }
+
+    /// The directives from startup, restored by `reset` and after a TTL runs out.
+    pub fn default_directives(&self) -> &str {
+        &self.default_directives
+    }
+
+    /// Time left until a temporary change reverts.
+    pub fn revert_in(&self) -> Option<Duration> {
+        self.state()
+            .revert_at
+            .map(|revert_at| revert_at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Replaces the filter, e.g. with `info,my_crate=trace`, until the next change.
+    pub fn set(&self, directives: &str) -> Result<(), LogLevelError> {
+        self.apply(directives, None).map(|_| ())
+    }
+
+    /// Replaces the filter and reverts to the defaults after `ttl`, unless changed again before.
+    /// Must be called from within a tokio runtime.
+    pub fn set_for(&self, directives: &str, ttl: Duration) -> Result<(), LogLevelError> {
+        let generation = self.apply(directives, Some(ttl))?;
+        let handle = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(ttl).await;
+            if handle.state().generation == generation {
+                info!("log filter TTL of {:?} expired, reverting", ttl);
+                if let Err(err) = handle.reset() {
+                    warn!("{}", err);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    pub fn reset(&self) -> Result<(), LogLevelError> {
+        self.set(&self.default_directives)
+    }
+
+    fn apply(&self, directives: &str, ttl: Option<Duration>) -> Result<u64, LogLevelError> {
+        let filter = EnvFilter::try_new(directives).map_err(LogLevelError::Invalid)?;
+        let mut state = self.state();
+        self.handle.reload(filter).map_err(LogLevelError::Reload)?;
+        state.directives = directives.to_owned();
+        state.generation += 1;
+        state.revert_at = ttl.map(|ttl| Instant::now() + ttl);
+        let generation = state.generation;
+        drop(state);
+
+        match ttl {
+            Some(ttl) => info!("log filter set to {:?} for {:?}", directives, ttl),
+            None => info!("log filter set to {:?}", directives),
+        }
+        Ok(generation)
+    }
+
+    fn state(&self) -> MutexGuard<'_, LogLevelState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for LogLevelHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogLevelHandle")
+            .field("directives", &self.directives())
+            .field("default_directives", &self.default_directives)
+            .finish()
+    }
+}
+
+/// Reloads the log filter on SIGHUP and whenever the watched file changes.
+///
+/// The file holds filter directives, one or more per line; blank lines and lines starting with
+/// `#` are ignored, and a file without directives restores the defaults. Without a file, SIGHUP
+/// restores the defaults.
+pub struct LogLevelWatcher {
+    handle: LogLevelHandle,
+    file: Option<PathBuf>,
+    poll_interval: Duration,
+    reload_rx: Option<watch::Receiver<u64>>,
+}
+
+impl LogLevelWatcher {
+    pub fn new(handle: LogLevelHandle) -> Self {
+        LogLevelWatcher {
+            handle,
+            file: None,
+            poll_interval: Duration::from_secs(5),
+            reload_rx: None,
+        }
+    }
+
+    pub fn with_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    /// How often the file's modification time is checked.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Reload events, usually `SignalListener::reload_receiver`.
+    pub fn with_reload_receiver(mut self, reload_rx: watch::Receiver<u64>) -> Self {
+        self.reload_rx = Some(reload_rx);
+        self
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        let file = self.file.as_ref()?;
+        std::fs::metadata(file)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    fn reload(&self) {
+        let Some(file) = &self.file else {
+            if let Err(err) = self.handle.reset() {
+                warn!("{}", err);
+            }
+            return;
+        };
+
+        let contents = match std::fs::read_to_string(file) {
+            Ok(contents) => contents,
+            Err(err) => {
+                warn!("failed to read log filter from {}: {}", file.display(), err);
+                return;
+            }
+        };
+        let directives = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let result = if directives.is_empty() {
+            self.handle.reset()
+        } else {
+            self.handle.set(&directives)
+        };
+        if let Err(err) = result {
+            warn!(
+                "keeping the current log filter, {} in {}",
+                err,
+                file.display()
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl StarlightService for LogLevelWatcher {
+    fn name(&self) -> &str {
+        "log-level-watcher"
+    }
+
+    async fn run(&self, shutdown: ShutdownToken) -> ServiceResult {
+        let mut reload_rx = self.reload_rx.clone();
+        let mut modified = self.modified();
+        if modified.is_some() {
+            self.reload();
+        }
+
+        let mut poll = tokio::time::interval(self.poll_interval);
+        poll.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            let reload_requested = async {
+                match reload_rx.as_mut() {
+                    Some(reload_rx) => {
+                        if reload_rx.changed().await.is_err() {
+                            std::future::pending::<()>().await;
+                        }
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                _ = reload_requested => self.reload(),
+                _ = poll.tick(), if self.file.is_some() => {
+                    let current = self.modified();
+                    if current != modified {
+                        modified = current;
+                        if current.is_some() {
+                            self.reload();
+                        }
+                    }
+                }
+                _ = shutdown.draining() => return Ok(()),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct LogLevelBody {
+    directives: String,
+    default: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revert_in_secs: Option<u64>,
+}
+
+impl From<&LogLevelHandle> for LogLevelBody {
+    fn from(handle: &LogLevelHandle) -> Self {
+        LogLevelBody {
+            directives: handle.directives(),
+            default: handle.default_directives().to_owned(),
+            revert_in_secs: handle.revert_in().map(|left| left.as_secs()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LogLevelUpdate {
+    /// Restores the defaults when absent.
+    directives: Option<String>,
+    ttl_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+/// Router exposing `GET /admin/log-level` and `PUT /admin/log-level`, which takes
+/// `{"directives": "info,my_crate=debug", "ttl_secs": 600}`. It is not authenticated; keep it off
+/// public listeners or put it behind the service's own auth.
+pub fn log_level_router(handle: LogLevelHandle) -> Router {
+    Router::new()
+        .route("/admin/log-level", get(get_handler).put(put_handler))
+        .with_state(handle)
+}
+
+async fn get_handler(State(handle): State<LogLevelHandle>) -> Response {
+    Json(LogLevelBody::from(&handle)).into_response()
+}
+
+async fn put_handler(
+    State(handle): State<LogLevelHandle>,
+    Json(update): Json<LogLevelUpdate>,
+) -> Response {
+    let directives = update
+        .directives
+        .unwrap_or_else(|| handle.default_directives().to_owned());
+    let result = match update.ttl_secs {
+        Some(ttl) => handle.set_for(&directives, Duration::from_secs(ttl)),
+        None => handle.set(&directives),
+    };
+
+    match result {
+        Ok(()) => Json(LogLevelBody::from(&handle)).into_response(),
+        Err(err @ LogLevelError::Invalid(_)) => {
+            let body = ErrorBody {
+                error: err.to_string(),
+            };
+            (StatusCode::BAD_REQUEST, Json(body)).into_response()
+        }
+        Err(err) => {
+            let body = ErrorBody {
+                error: err.to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(body)).into_response()
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use crate::logger::{LogTimezone, TimestampFormat, trace_context};
+use std::fmt::{self, Write};
+use tracing::field::{Field, Visit};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Formats events as logfmt, one `key=value` pair per field:
+///
+/// ```text
+/// ts=2025-01-31T12:00:00.123Z level=info trace_id=4bf92f35... span_id=00f067aa... pid=42 thread=main target=my_crate module=my_crate::http msg="request handled" status=200
+/// ```
+///
+/// Values with spaces, quotes, `=` or control characters are quoted and escaped. The fields
+/// are those of `CustomLogFormatter`, followed by the event's own fields.
+#[derive(Debug, Clone)]
+pub struct LogfmtFormatter {
+    timezone: LogTimezone,
+    timestamp_format: TimestampFormat,
+}
+
+impl Default for LogfmtFormatter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogfmtFormatter {
+    /// RFC 3339 timestamps in UTC.
+    pub fn new() -> Self {
+        LogfmtFormatter {
+            timezone: LogTimezone::Utc,
+            timestamp_format: TimestampFormat::Rfc3339,
+        }
+    }
+
+    pub fn with_timezone(mut self, timezone: LogTimezone) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    pub fn with_timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.timestamp_format = timestamp_format;
+        self
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for LogfmtFormatter
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &tracing::Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let now = self.timezone.now();
+        let timestamp = Timestamp {
+            now,
+            format: &self.timestamp_format,
+        };
+        write_pair(&mut writer, "ts", format_args!("{}", timestamp))?;
+
+        let level = match *metadata.level() {
+            tracing::Level::TRACE => "trace",
+            tracing::Level::DEBUG => "debug",
+            tracing::Level::INFO => "info",
+            tracing::Level::WARN => "warn",
+            tracing::Level::ERROR => "error",
+        };
+        write!(writer, " level={}", level)?;
+
+        let (trace_id, span_id) = trace_context(ctx.parent_span());
+        write!(writer, " trace_id={:032x} span_id={:016x}", trace_id, span_id)?;
+
+        write!(writer, " pid={}", std::process::id())?;
+        let thread = std::thread::current();
+        writer.write_char(' ')?;
+        match thread.name() {
+            Some(name) => write_pair(&mut writer, "thread", format_args!("{}", name))?,
+            None => write_pair(&mut writer, "thread", format_args!("{:?}", thread.id()))?,
+        }
+
+        writer.write_char(' ')?;
+        write_pair(&mut writer, "target", format_args!("{}", metadata.target()))?;
+        if let Some(module_path) = metadata.module_path() {
+            writer.write_char(' ')?;
+            write_pair(&mut writer, "module", format_args!("{}", module_path))?;
+        }
+
+        let mut visitor = LogfmtVisitor {
+            writer: &mut writer,
+            result: Ok(()),
+        };
+        event.record(&mut visitor);
+        visitor.result?;
+        writeln!(writer)
+    }
+}
+
+struct Timestamp<'a> {
+    now: time::OffsetDateTime,
+    format: &'a TimestampFormat,
+}
+
+impl fmt::Display for Timestamp<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.format.write(self.now, f)
+    }
+}
+
+/// Writes every field as ` key=value`, with `message` renamed to `msg`.
+struct LogfmtVisitor<'a, 'b> {
+    writer: &'a mut Writer<'b>,
+    result: fmt::Result,
+}
+
+impl LogfmtVisitor<'_, '_> {
+    fn record(&mut self, field: &Field, value: fmt::Arguments<'_>) {
+        // Added by tracing-log for events from the `log` crate; already covered above.
+        if self.result.is_err() || field.name().starts_with("log.") {
+            return;
+        }
+        let key = match field.name() {
+            "message" => "msg",
+            name => name,
+        };
+        self.result = self
+            .writer
+            .write_char(' ')
+            .and_then(|_| write_pair(self.writer, key, value));
+    }
+}
+
+impl Visit for LogfmtVisitor<'_, '_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, format_args!("{}", value));
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.record(field, format_args!("{}", value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record(field, format_args!("{:?}", value));
+    }
+}
+
+/// Writes `key=value`, quoting the value if needed. The value is formatted twice, once to
+/// find out whether it needs quotes, so that nothing has to be buffered.
+fn write_pair(writer: &mut impl Write, key: &str, value: fmt::Arguments<'_>) -> fmt::Result {
+    let mut scan = QuotingScan::default();
+    scan.write_fmt(value)?;
+
+    write!(writer, "{}=", key)?;
+    if scan.needs_quotes() {
+        writer.write_char('"')?;
+        Escaped(writer).write_fmt(value)?;
+        writer.write_char('"')
+    } else {
+        writer.write_fmt(value)
+    }
+}
+
+#[derive(Default)]
+struct QuotingScan {
+    written: bool,
+    special: bool,
+}
+
+impl QuotingScan {
+    fn needs_quotes(&self) -> bool {
+        !self.written || self.special
+    }
+}
+
+impl Write for QuotingScan {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.written |= !s.is_empty();
+        self.special |= s
+            .chars()
+            .any(|c| c == ' ' || c == '=' || c == '"' || c == '\\' || c.is_control());
+        Ok(())
+    }
+}
+
+/// Escapes quotes, backslashes and control characters inside a quoted value.
+struct Escaped<'a, W: ?Sized>(&'a mut W);
+
+impl<W: Write + ?Sized> Write for Escaped<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '"' => self.0.write_str("\\\"")?,
+                '\\' => self.0.write_str("\\\\")?,
+                '\n' => self.0.write_str("\\n")?,
+                '\r' => self.0.write_str("\\r")?,
+                '\t' => self.0.write_str("\\t")?,
+                c if c.is_control() => write!(self.0, "\\u{{{:x}}}", c as u32)?,
+                c => self.0.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    fn pair(value: fmt::Arguments<'_>) -> String {
+        let mut line = String::new();
+        write_pair(&mut line, "key", value).unwrap();
+        line
+    }
+
+    #[test]
+    fn leaves_plain_values_unquoted() {
+        assert_eq!(pair(format_args!("value")), "key=value");
+        assert_eq!(pair(format_args!("{}", 200)), "key=200");
+        assert_eq!(pair(format_args!("a/b:c.d-é")), "key=a/b:c.d-é");
+    }
+
+    #[test]
+    fn quotes_empty_values() {
+        assert_eq!(pair(format_args!("")), "key=\"\"");
+    }
+
+    #[test]
+    fn quotes_values_with_special_characters() {
+        assert_eq!(pair(format_args!("two words")), "key=\"two words\"");
+        assert_eq!(pair(format_args!("a=b")), "key=\"a=b\"");
+    }
+
+    #[test]
+    fn escapes_quotes_backslashes_and_control_characters() {
+        assert_eq!(pair(format_args!("say \"hi\"")), r#"key="say \"hi\"""#);
+        assert_eq!(pair(format_args!(r"C:\temp")), r#"key="C:\\temp""#);
+        assert_eq!(pair(format_args!("a\nb\r\tc")), r#"key="a\nb\r\tc""#);
+        assert_eq!(pair(format_args!("bell\u{7}")), r#"key="bell\u{7}""#);
+    }
+
+    #[test]
+    fn decides_on_quotes_across_formatting_pieces() {
+        let (first, second) = ("plain", "with space");
+        assert_eq!(
+            pair(format_args!("{}{}", first, second)),
+            "key=\"plainwith space\""
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn formats_event_fields() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .event_format(LogfmtFormatter::new())
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            info!(path = "/a b", status = 200, "request \"handled\"");
+        });
+
+        let line = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(line.starts_with("ts="), "{}", line);
+        assert!(line.contains(" level=info "), "{}", line);
+        let fields = " msg=\"request \\\"handled\\\"\" path=\"/a b\" status=200\n";
+        assert!(line.ends_with(fields), "{}", line);
+    }
+}
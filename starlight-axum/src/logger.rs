@@ -1,15 +1,22 @@
+use crate::logfmt::LogfmtFormatter;
 use crate::resource::get_resource;
 use opentelemetry_otlp::{LogExporter, WithExportConfig};
 use opentelemetry_sdk::logs::SdkLoggerProvider;
+use opentelemetry::trace::{SpanId, TraceId};
 use std::fmt::Debug;
+use std::io;
 use std::net::SocketAddr;
 use std::sync::OnceLock;
+use time::error::InvalidFormatDescription;
+use time::format_description::OwnedFormatItem;
+use time::format_description::well_known::Rfc3339;
 use time::{OffsetDateTime, format_description};
-use time_tz::ToTimezone;
-use tracing_opentelemetry::OpenTelemetrySpanExt;
+use time_tz::{ToTimezone, Tz};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::fmt::FormattedFields;
 use tracing_subscriber::fmt::format::Writer;
 use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
-use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
 
 static SDK_LOGGER_PROVIDER: OnceLock<SdkLoggerProvider> = OnceLock::new();
 pub fn get_logger_provider() -> &'static SdkLoggerProvider {
@@ -18,6 +25,11 @@ pub fn get_logger_provider() -> &'static SdkLoggerProvider {
         .expect("Failed to get a logger provider")
 }
 
+/// The provider, if one was installed.
+pub(crate) fn try_get_logger_provider() -> Option<&'static SdkLoggerProvider> {
+    SDK_LOGGER_PROVIDER.get()
+}
+
 pub fn get_or_init_logger_provider(oltp_grpc_url: &str) -> SdkLoggerProvider {
     SDK_LOGGER_PROVIDER
         .get_or_init(|| {
@@ -35,12 +47,283 @@ pub fn get_or_init_logger_provider(oltp_grpc_url: &str) -> SdkLoggerProvider {
         .clone()
 }
 
-#[derive(Debug)]
-pub struct CustomLogFormatter;
+/// Provider without an exporter, for when OTLP logs are turned off.
+pub fn get_or_init_noop_logger_provider() -> SdkLoggerProvider {
+    SDK_LOGGER_PROVIDER
+        .get_or_init(|| {
+            SdkLoggerProvider::builder()
+                .with_resource(get_resource())
+                .build()
+        })
+        .clone()
+}
+
+const DEFAULT_TIMESTAMP_FORMAT: &str = "[year]-[month]-[day] [hour]:[minute]:[second].[subsecond digits:3] [offset_hour sign:mandatory]:[offset_minute]";
+
+/// Timezone of log timestamps, resolved once when the formatter is built.
+#[derive(Debug, Clone, Copy)]
+pub enum LogTimezone {
+    Utc,
+    Named(&'static Tz),
+}
+
+impl LogTimezone {
+    /// The system timezone, or UTC where it cannot be determined, e.g. without tz data.
+    pub fn local() -> Self {
+        time_tz::system::get_timezone()
+            .map(LogTimezone::Named)
+            .unwrap_or(LogTimezone::Utc)
+    }
+
+    /// An IANA timezone such as `Europe/Berlin`.
+    pub fn named(name: &str) -> Option<Self> {
+        time_tz::timezones::get_by_name(name).map(LogTimezone::Named)
+    }
+
+    pub(crate) fn now(&self) -> OffsetDateTime {
+        let now = OffsetDateTime::now_utc();
+        match self {
+            LogTimezone::Utc => now,
+            LogTimezone::Named(tz) => now.to_timezone(*tz),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum TimestampFormat {
+    Rfc3339,
+    /// A `time` format description, parsed once up front.
+    Custom(OwnedFormatItem),
+    /// Milliseconds since the Unix epoch.
+    EpochMillis,
+}
+
+impl TimestampFormat {
+    pub fn custom(description: &str) -> Result<Self, InvalidFormatDescription> {
+        format_description::parse_owned::<1>(description).map(TimestampFormat::Custom)
+    }
+}
+
+impl TimestampFormat {
+    pub(crate) fn write(&self, now: OffsetDateTime, writer: &mut impl std::fmt::Write) -> std::fmt::Result {
+        let mut output = FmtWriter(writer);
+        let result = match self {
+            TimestampFormat::Rfc3339 => now.format_into(&mut output, &Rfc3339),
+            TimestampFormat::Custom(format) => now.format_into(&mut output, format),
+            TimestampFormat::EpochMillis => {
+                return write!(writer, "{}", now.unix_timestamp_nanos() / 1_000_000);
+            }
+        };
+        result.map(|_| ()).map_err(|_| std::fmt::Error)
+    }
+}
+
+impl Default for TimestampFormat {
+    /// `2025-01-31 12:00:00.000 +01:00`
+    fn default() -> Self {
+        TimestampFormat::custom(DEFAULT_TIMESTAMP_FORMAT).expect("wrong time format")
+    }
+}
+
+/// How the module path of an event is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModulePathStyle {
+    /// `my_crate::http::handlers`
+    Full,
+    /// `m::h::handlers`
+    Abbreviated,
+}
+
+/// Formats events as
+/// `timestamp LEVEL [trace_id,span_id] [pid,thread] [target] m::module: spans: fields`,
+/// where each segment can be turned off and the span hierarchy is off by default.
+/// Writes straight into the output buffer, so formatting an event does not allocate.
+#[derive(Debug, Clone)]
+pub struct CustomLogFormatter {
+    timezone: LogTimezone,
+    timestamp_format: TimestampFormat,
+    timestamp: bool,
+    level: bool,
+    trace_context: bool,
+    pid: bool,
+    thread_name: bool,
+    thread_id: bool,
+    target: bool,
+    module_path: Option<ModulePathStyle>,
+    spans: bool,
+}
+
+impl Default for CustomLogFormatter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl CustomLogFormatter {
-    fn new() -> Self {
-        CustomLogFormatter {}
+    /// Local time in the default format, with every segment but the span hierarchy.
+    pub fn new() -> Self {
+        CustomLogFormatter {
+            timezone: LogTimezone::local(),
+            timestamp_format: TimestampFormat::default(),
+            timestamp: true,
+            level: true,
+            trace_context: true,
+            pid: true,
+            thread_name: true,
+            thread_id: false,
+            target: true,
+            module_path: Some(ModulePathStyle::Abbreviated),
+            spans: false,
+        }
+    }
+
+    pub fn with_timezone(mut self, timezone: LogTimezone) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    pub fn with_timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.timestamp_format = timestamp_format;
+        self
+    }
+
+    pub fn with_timestamp(mut self, enabled: bool) -> Self {
+        self.timestamp = enabled;
+        self
+    }
+
+    pub fn with_level(mut self, enabled: bool) -> Self {
+        self.level = enabled;
+        self
+    }
+
+    /// The OpenTelemetry trace and span id of the current span.
+    pub fn with_trace_context(mut self, enabled: bool) -> Self {
+        self.trace_context = enabled;
+        self
+    }
+
+    pub fn with_pid(mut self, enabled: bool) -> Self {
+        self.pid = enabled;
+        self
+    }
+
+    /// Unnamed threads are shown by their id instead.
+    pub fn with_thread_name(mut self, enabled: bool) -> Self {
+        self.thread_name = enabled;
+        self
+    }
+
+    pub fn with_thread_id(mut self, enabled: bool) -> Self {
+        self.thread_id = enabled;
+        self
+    }
+
+    pub fn with_target(mut self, enabled: bool) -> Self {
+        self.target = enabled;
+        self
+    }
+
+    /// `None` leaves the module path out.
+    pub fn with_module_path(mut self, style: Option<ModulePathStyle>) -> Self {
+        self.module_path = style;
+        self
+    }
+
+    /// The spans the event is in, from the root, with their fields: `request{id=7}:db: `.
+    pub fn with_spans(mut self, enabled: bool) -> Self {
+        self.spans = enabled;
+        self
+    }
+
+}
+
+/// Output format of a log sink.
+#[derive(Debug, Clone)]
+pub enum LogFormat {
+    Custom(CustomLogFormatter),
+    Logfmt(LogfmtFormatter),
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Custom(CustomLogFormatter::new())
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for LogFormat
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        writer: Writer<'_>,
+        event: &tracing::Event<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            LogFormat::Custom(formatter) => formatter.format_event(ctx, writer, event),
+            LogFormat::Logfmt(formatter) => formatter.format_event(ctx, writer, event),
+        }
+    }
+}
+
+/// Writes `root{fields}:...:span{fields}:`. Walks up recursively instead of through
+/// `Scope::from_root`, which collects the spans into a `Vec`.
+fn write_span_hierarchy<S, N>(writer: &mut Writer<'_>, span: &SpanRef<'_, S>) -> std::fmt::Result
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    if let Some(parent) = span.parent() {
+        write_span_hierarchy::<S, N>(writer, &parent)?;
+    }
+    writer.write_str(span.name())?;
+    let extensions = span.extensions();
+    if let Some(fields) = extensions.get::<FormattedFields<N>>()
+        && !fields.is_empty()
+    {
+        write!(writer, "{{{}}}", fields)?;
+    }
+    writer.write_char(':')
+}
+
+/// The OpenTelemetry ids of `span`, read from its `OtelData` rather than by building an otel
+/// `Context` for it.
+pub(crate) fn trace_context<S>(span: Option<SpanRef<'_, S>>) -> (TraceId, SpanId)
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    use opentelemetry::trace::TraceContextExt;
+
+    span.and_then(|span| {
+        let extensions = span.extensions();
+        let otel = extensions.get::<OtelData>()?;
+        // Root spans get a random `builder.trace_id` that `set_parent` does not clear, so a
+        // propagated parent takes precedence.
+        let trace_id = if otel.parent_cx.has_active_span() {
+            otel.parent_cx.span().span_context().trace_id()
+        } else {
+            otel.builder.trace_id.unwrap_or(TraceId::INVALID)
+        };
+        Some((trace_id, otel.builder.span_id.unwrap_or(SpanId::INVALID)))
+    })
+    .unwrap_or((TraceId::INVALID, SpanId::INVALID))
+}
+
+/// Lets `time` format into the log line instead of a temporary `String`.
+struct FmtWriter<'a, W: ?Sized>(&'a mut W);
+
+impl<W: std::fmt::Write + ?Sized> io::Write for FmtWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = std::str::from_utf8(buf).map_err(io::Error::other)?;
+        self.0.write_str(text).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
 }
 
@@ -55,88 +338,93 @@ where
         mut writer: Writer<'_>,
         event: &tracing::Event<'_>,
     ) -> std::fmt::Result {
-        use opentelemetry::trace::TraceContextExt;
-
-        let system_tz = time_tz::system::get_timezone().expect("Failed to find system timezone");
-        let now = OffsetDateTime::now_utc().to_timezone(system_tz);
-
-        let format = format_description::parse("[year]-[month]-[day] [hour]:[minute]:[second].[subsecond digits:3] [offset_hour sign:mandatory]:[offset_minute]").expect("wrong time format");
-        let timestamp = now.format(&format).expect("Failed to get a timestamp");
-        write!(writer, "{}", timestamp)?;
-
-        let level = event.metadata().level();
-        // Format Log Level
-        match *level {
-            tracing::Level::TRACE => write!(
-                writer,
-                " {}",
-                ansi_term::Colour::Purple.bold().paint(level.as_str())
-            ),
-            tracing::Level::DEBUG => write!(
-                writer,
-                " {}",
-                ansi_term::Colour::Blue.bold().paint(level.as_str())
-            ),
-            tracing::Level::INFO => write!(
-                writer,
-                " {}",
-                ansi_term::Colour::Green.bold().paint(level.as_str())
-            ),
-            tracing::Level::WARN => write!(
-                writer,
-                " {}",
-                ansi_term::Colour::Yellow.bold().paint(level.as_str())
-            ),
-            tracing::Level::ERROR => write!(
-                writer,
-                " {}",
-                ansi_term::Colour::Red.bold().paint(level.as_str())
-            ),
-        }?;
-
-        let current_span = tracing::Span::current();
-        let trace_id = current_span.context().span().span_context().trace_id();
-        let span_id = current_span.context().span().span_context().span_id();
-
-        // Decorate Span info
-        // let ctx1 = opentelemetry::Context::current();
-        // let trace_id = ctx1.span().span_context().trace_id();
-        // let span_id = ctx1.span().span_context().span_id();
-        write!(writer, " [{:x},{:x}]", trace_id, span_id)?;
-
-        // get some process information
-        let pid = std::process::id();
-        let thread = std::thread::current();
-        let thread_name = thread.name();
-        write!(writer, " [{},{:?}]", pid, thread_name.unwrap())?;
-
-        // Format target
-        let target = event.metadata().target();
-        write!(
-            writer,
-            " [{}]",
-            ansi_term::Colour::Blue.bold().paint(target)
-        )?;
-
-        // Format Module
-        let module_split = event.metadata().module_path().expect("").split("::");
-        let count = module_split.clone().count();
-        let mut module_short = String::new();
-        for (pos, module) in module_split.enumerate() {
-            if pos == count - 1 {
-                module_short.push_str(module);
-            } else {
-                module_short.push(module.chars().next().unwrap());
-                module_short.push_str("::");
+        let metadata = event.metadata();
+        // Segments are separated by a space, so only the first one goes without.
+        let mut separator = "";
+
+        if self.timestamp {
+            self.timestamp_format.write(self.timezone.now(), &mut writer)?;
+            separator = " ";
+        }
+
+        if self.level {
+            let level = metadata.level();
+            let colour = match *level {
+                tracing::Level::TRACE => ansi_term::Colour::Purple,
+                tracing::Level::DEBUG => ansi_term::Colour::Blue,
+                tracing::Level::INFO => ansi_term::Colour::Green,
+                tracing::Level::WARN => ansi_term::Colour::Yellow,
+                tracing::Level::ERROR => ansi_term::Colour::Red,
+            };
+            write!(writer, "{}{}", separator, colour.bold().paint(level.as_str()))?;
+            separator = " ";
+        }
+
+        if self.trace_context {
+            let (trace_id, span_id) = trace_context(ctx.parent_span());
+            write!(writer, "{}[{:x},{:x}]", separator, trace_id, span_id)?;
+            separator = " ";
+        }
+
+        if self.pid || self.thread_name || self.thread_id {
+            let thread = std::thread::current();
+            let thread_name = thread.name().filter(|_| self.thread_name);
+            let show_thread_id = self.thread_id || (self.thread_name && thread_name.is_none());
+
+            write!(writer, "{}[", separator)?;
+            let mut part_separator = "";
+            if self.pid {
+                write!(writer, "{}", std::process::id())?;
+                part_separator = ",";
+            }
+            if let Some(thread_name) = thread_name {
+                write!(writer, "{}{:?}", part_separator, thread_name)?;
+                part_separator = ",";
             }
+            if show_thread_id {
+                write!(writer, "{}{:?}", part_separator, thread.id())?;
+            }
+            writer.write_char(']')?;
+            separator = " ";
+        }
+
+        if self.target {
+            let target = ansi_term::Colour::Blue.bold().paint(metadata.target());
+            write!(writer, "{}[{}]", separator, target)?;
+            separator = " ";
         }
 
-        write!(
-            writer,
-            " {}: ",
-            ansi_term::Colour::Yellow.bold().paint(module_short)
-        )?;
+        if let Some(style) = self.module_path {
+            let module_path = metadata.module_path().unwrap_or(metadata.target());
+            let colour = ansi_term::Colour::Yellow.bold();
+            write!(writer, "{}{}", separator, colour.prefix())?;
+            match style {
+                ModulePathStyle::Full => writer.write_str(module_path)?,
+                ModulePathStyle::Abbreviated => {
+                    // All but the last segment are cut to their first letter.
+                    let mut segments = module_path.split("::").peekable();
+                    while let Some(segment) = segments.next() {
+                        if segments.peek().is_none() {
+                            writer.write_str(segment)?;
+                        } else if let Some(initial) = segment.chars().next() {
+                            write!(writer, "{}::", initial)?;
+                        }
+                    }
+                }
+            }
+            write!(writer, "{}:", colour.suffix())?;
+            separator = " ";
+        }
+
+        if self.spans
+            && let Some(span) = ctx.parent_span()
+        {
+            writer.write_str(separator)?;
+            write_span_hierarchy::<S, N>(&mut writer, &span)?;
+            separator = " ";
+        }
 
+        writer.write_str(separator)?;
         ctx.field_format().format_fields(writer.by_ref(), event)?;
         writeln!(writer)
     }
@@ -145,14 +433,13 @@ where
 use axum::body::Body;
 use axum::body::Bytes;
 use axum::extract::Request;
-use axum::{http, Extension};
+use axum::http;
 use axum::http::{Extensions, StatusCode};
 use axum::http::header;
 use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
 use http_body_util::BodyExt;
 use std::time::Instant;
-use headers::HeaderValue;
 
 pub async fn print_request_response(
     req: Request,
@@ -201,7 +488,7 @@ where
         info!("ip: {:#?}", source_addr.ip().to_string());
     }
 
-    if let Some(_) = headers.get(header::AUTHORIZATION) {
+    if headers.get(header::AUTHORIZATION).is_some() {
         info!("{:#?}: \"****************************\"", header::AUTHORIZATION.as_str());
     }
 
@@ -1,47 +1,307 @@
-use crate::resource::get_resource;
+use crate::resource::{get_resource, get_service_identity};
 use opentelemetry::metrics::Meter;
 use opentelemetry::{InstrumentationScope, global};
 use opentelemetry_otlp::{MetricExporter, WithExportConfig};
-use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
-use std::sync::{LazyLock, OnceLock};
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_semantic_conventions::metric::{
+    HTTP_SERVER_ACTIVE_REQUESTS, HTTP_SERVER_REQUEST_BODY_SIZE, HTTP_SERVER_REQUEST_DURATION,
+    HTTP_SERVER_RESPONSE_BODY_SIZE,
+};
+use opentelemetry_sdk::metrics::data::ResourceMetrics;
+use opentelemetry_sdk::metrics::reader::MetricReader;
+use opentelemetry_sdk::metrics::{
+    InstrumentKind, MetricResult, PeriodicReader, Pipeline, SdkMeterProvider, Temporality,
+};
+use std::sync::{LazyLock, Mutex, OnceLock, PoisonError, Weak};
 use std::time::Duration;
-use crate::get_env_or_panic;
+use crate::exemplar::ExemplarExporter;
+use crate::prometheus::get_or_init_prometheus_exporter;
+use crate::get_env_or_default;
 
 static SDK_METER_PROVIDER: OnceLock<SdkMeterProvider> = OnceLock::new();
 
+/// Warnings about the environment raised before a subscriber was there to log them; `None` once
+/// they have been logged.
+static ENV_WARNINGS: Mutex<Option<Vec<String>>> = Mutex::new(Some(vec![]));
+
+/// Export settings for the OTLP metric pipeline.
+#[derive(Debug, Clone)]
+pub struct MeterConfig {
+    export_interval: Duration,
+    export_timeout: Duration,
+    temporality: Temporality,
+    temporality_overrides: Vec<(InstrumentKind, Temporality)>,
+}
+
+impl Default for MeterConfig {
+    fn default() -> Self {
+        MeterConfig {
+            export_interval: Duration::from_secs(5),
+            export_timeout: Duration::from_secs(30),
+            temporality: Temporality::Cumulative,
+            temporality_overrides: vec![],
+        }
+    }
+}
+
+impl MeterConfig {
+    /// Reads `OTEL_METRIC_EXPORT_INTERVAL`, `OTEL_METRIC_EXPORT_TIMEOUT` (both in milliseconds)
+    /// and `OTEL_EXPORTER_OTLP_METRICS_TEMPORALITY_PREFERENCE`, keeping defaults for unset values.
+    /// Invalid values are warned about and ignored, as the spec asks.
+    pub fn from_env() -> Self {
+        let mut config = MeterConfig::default();
+        if let Some(interval) = get_env_millis("OTEL_METRIC_EXPORT_INTERVAL") {
+            config = config.with_export_interval(interval);
+        }
+        if let Some(timeout) = get_env_millis("OTEL_METRIC_EXPORT_TIMEOUT") {
+            config = config.with_export_timeout(timeout);
+        }
+        if let Ok(preference) = std::env::var("OTEL_EXPORTER_OTLP_METRICS_TEMPORALITY_PREFERENCE")
+            && let Some(temporality) = parse_temporality(&preference)
+        {
+            config = config.with_temporality(temporality);
+        }
+        config
+    }
+
+    pub fn with_export_interval(mut self, interval: Duration) -> Self {
+        if !interval.is_zero() {
+            self.export_interval = interval;
+        }
+        self
+    }
+
+    pub fn with_export_timeout(mut self, timeout: Duration) -> Self {
+        if !timeout.is_zero() {
+            self.export_timeout = timeout;
+        }
+        self
+    }
+
+    /// Sets the temporality preference, mapped per instrument kind as the OTLP spec describes.
+    pub fn with_temporality(mut self, temporality: Temporality) -> Self {
+        self.temporality = temporality;
+        self
+    }
+
+    /// Pins the temporality of one instrument kind, taking precedence over the preference.
+    pub fn with_instrument_temporality(mut self, kind: InstrumentKind, temporality: Temporality) -> Self {
+        self.temporality_overrides.retain(|(existing, _)| *existing != kind);
+        self.temporality_overrides.push((kind, temporality));
+        self
+    }
+
+    pub fn export_interval(&self) -> Duration {
+        self.export_interval
+    }
+
+    pub fn export_timeout(&self) -> Duration {
+        self.export_timeout
+    }
+
+    pub fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.temporality_overrides
+            .iter()
+            .find(|(existing, _)| *existing == kind)
+            .map(|(_, temporality)| *temporality)
+            .unwrap_or_else(|| temporality_preference(kind, self.temporality))
+    }
+}
+
+fn get_env_millis(variable: &str) -> Option<Duration> {
+    parse_millis(variable, &std::env::var(variable).ok()?)
+}
+
+fn parse_millis(variable: &str, value: &str) -> Option<Duration> {
+    match value.trim().parse::<u64>() {
+        Ok(millis) => Some(Duration::from_millis(millis)),
+        Err(_) => {
+            warn_invalid_env(variable, value, "a number of milliseconds");
+            None
+        }
+    }
+}
+
+fn parse_temporality(preference: &str) -> Option<Temporality> {
+    match preference.trim().to_lowercase().as_str() {
+        "cumulative" => Some(Temporality::Cumulative),
+        "delta" => Some(Temporality::Delta),
+        "lowmemory" => Some(Temporality::LowMemory),
+        _ => {
+            warn_invalid_env(
+                "OTEL_EXPORTER_OTLP_METRICS_TEMPORALITY_PREFERENCE",
+                preference,
+                "cumulative, delta or lowmemory",
+            );
+            None
+        }
+    }
+}
+
+/// The variables are usually read before `config_oltp` installs the subscriber, which would
+/// drop the event, so the warning is held back until `emit_env_warnings`.
+fn warn_invalid_env(variable: &str, value: &str, expected: &str) {
+    let warning = format!("ignoring {}={:?}, expected {}", variable, value, expected);
+    match ENV_WARNINGS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_mut()
+    {
+        Some(warnings) => warnings.push(warning),
+        None => warn!("{}", warning),
+    }
+}
+
+/// Logs the warnings held back by `warn_invalid_env`, once the subscriber is installed.
+pub(crate) fn emit_env_warnings() {
+    let warnings = ENV_WARNINGS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+    for warning in warnings.into_iter().flatten() {
+        warn!("{}", warning);
+    }
+}
+
+/// A reader named in `OTEL_METRICS_EXPORTER`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MetricsExporter {
+    Otlp,
+    Prometheus,
+}
+
+/// Parses `OTEL_METRICS_EXPORTER`, a comma-separated list such as `otlp,prometheus`.
+fn parse_metrics_exporters(value: &str) -> Vec<MetricsExporter> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter_map(|exporter| match exporter {
+            "otlp" => Some(MetricsExporter::Otlp),
+            "prometheus" => Some(MetricsExporter::Prometheus),
+            "none" | "" => None,
+            other => {
+                warn_invalid_env("OTEL_METRICS_EXPORTER", other, "otlp, prometheus or none");
+                None
+            }
+        })
+        .collect()
+}
+
+fn temporality_preference(kind: InstrumentKind, temporality: Temporality) -> Temporality {
+    match (temporality, kind) {
+        (Temporality::Delta, InstrumentKind::UpDownCounter | InstrumentKind::ObservableUpDownCounter) => {
+            Temporality::Cumulative
+        }
+        (Temporality::LowMemory, InstrumentKind::Counter | InstrumentKind::Histogram) => Temporality::Delta,
+        (Temporality::LowMemory, _) => Temporality::Cumulative,
+        (temporality, _) => temporality,
+    }
+}
+
+/// Wraps a reader so the pipeline aggregates each instrument kind with the configured temporality.
+#[derive(Debug)]
+struct ConfiguredTemporalityReader<R> {
+    reader: R,
+    config: MeterConfig,
+}
+
+impl<R: MetricReader> MetricReader for ConfiguredTemporalityReader<R> {
+    fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+        self.reader.register_pipeline(pipeline)
+    }
+
+    fn collect(&self, rm: &mut ResourceMetrics) -> MetricResult<()> {
+        self.reader.collect(rm)
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.reader.force_flush()
+    }
+
+    fn shutdown(&self) -> OTelSdkResult {
+        self.reader.shutdown()
+    }
+
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.config.temporality(kind)
+    }
+}
+
 pub fn get_meter_provider() -> &'static SdkMeterProvider {
     SDK_METER_PROVIDER
         .get()
         .expect("failed to get meter provider")
 }
 
+/// The provider, if one was installed.
+pub(crate) fn try_get_meter_provider() -> Option<&'static SdkMeterProvider> {
+    SDK_METER_PROVIDER.get()
+}
+
 pub fn get_or_init_meter_provider(oltp_grpc_url: &str) -> SdkMeterProvider {
+    get_or_init_meter_provider_with(oltp_grpc_url, MeterConfig::from_env())
+}
+
+/// Like [`get_or_init_meter_provider`], with explicit export settings.
+/// Only the first call initializes the provider, so call it before `config_oltp` to take effect.
+pub fn get_or_init_meter_provider_with(oltp_grpc_url: &str, config: MeterConfig) -> SdkMeterProvider {
     SDK_METER_PROVIDER
         .get_or_init(|| {
-            let metric_exporter = MetricExporter::builder()
-                .with_tonic()
-                .with_endpoint(oltp_grpc_url)
-                .with_temporality(opentelemetry_sdk::metrics::Temporality::default())
-                .build()
-                .expect("failed to create metric exporter");
+            let mut builder = SdkMeterProvider::builder().with_resource(get_resource());
+
+            let exporters = get_env_or_default("OTEL_METRICS_EXPORTER", "otlp".to_owned());
+            for exporter in parse_metrics_exporters(&exporters) {
+                match exporter {
+                    MetricsExporter::Otlp => {
+                        let metric_exporter = MetricExporter::builder()
+                            .with_tonic()
+                            .with_endpoint(oltp_grpc_url)
+                            .with_timeout(config.export_timeout)
+                            .with_temporality(config.temporality)
+                            .build()
+                            .expect("failed to create metric exporter");
+
+                        builder = builder.with_reader(ConfiguredTemporalityReader {
+                            reader: PeriodicReader::builder(
+                                ExemplarExporter::new(metric_exporter)
+                                    .with_interval(config.export_interval),
+                            )
+                                .with_interval(config.export_interval)
+                                .build(),
+                            config: config.clone(),
+                        });
+                    }
+                    MetricsExporter::Prometheus => {
+                        builder = builder.with_reader(get_or_init_prometheus_exporter());
+                    }
+                }
+            }
+
+            builder.build()
+        })
+        .clone()
+}
 
+/// Provider without readers, for when metrics are turned off. Instruments created from
+/// `GLOBAL_METER` keep working and record nothing.
+pub fn get_or_init_noop_meter_provider() -> SdkMeterProvider {
+    SDK_METER_PROVIDER
+        .get_or_init(|| {
             SdkMeterProvider::builder()
-                .with_reader(
-                    PeriodicReader::builder(metric_exporter)
-                        .with_interval(Duration::from_secs(5))
-                        .build(),
-                )
                 .with_resource(get_resource())
                 .build()
         })
         .clone()
 }
 
+/// Meter scoped to the service identity, see `init_service_identity`.
 pub static GLOBAL_METER: LazyLock<Meter> = LazyLock::new(|| {
-    let scope = InstrumentationScope::builder(get_env_or_panic("CARGO_PKG_NAME"))
-        .with_version(get_env_or_panic("CARGO_PKG_VERSION"))
-        .build();
-    global::meter_with_scope(scope)
+    let identity = get_service_identity();
+    let mut scope = InstrumentationScope::builder(identity.name().to_owned());
+    if let Some(version) = identity.version() {
+        scope = scope.with_version(version.to_owned());
+    }
+    global::meter_with_scope(scope.build())
 });
 
 #[macro_export]
@@ -87,18 +347,21 @@ macro_rules! histogram {
             .with_unit($metric.unit())
             .build();
 
+        let value = $value;
         let labels = vec![$(KeyValue::new($label_key, $label_value)),*];
-        histogram.record($value, &labels);
+        histogram.record(value, &labels);
+        $crate::exemplar::record_exemplar($metric.name(), value, &labels);
     }};
 }
 
 macro_rules! predefine_metrics {
     // Match multiple variants, each with name, description, and unit.
-    ($($variant:ident { name: $name:expr, description: $description:expr, unit: $unit:expr }),* $(,)?) => {
+    ($($(#[$attribute:meta])* $variant:ident { name: $name:expr, description: $description:expr, unit: $unit:expr }),* $(,)?) => {
         pub enum Metric {
-            $($variant),*
+            $($(#[$attribute])* $variant),*
         }
 
+        #[allow(deprecated)]
         impl Metric {
             pub fn name(&self) -> &'static str {
                 match self {
@@ -122,14 +385,175 @@ macro_rules! predefine_metrics {
 }
 
 predefine_metrics! {
+    HttpServerRequestDuration {
+        name: HTTP_SERVER_REQUEST_DURATION,
+        description: "Duration of HTTP server requests",
+        unit: "s"
+    },
+    HttpServerActiveRequests {
+        name: HTTP_SERVER_ACTIVE_REQUESTS,
+        description: "Number of active HTTP server requests",
+        unit: "{request}"
+    },
+    HttpServerRequestBodySize {
+        name: HTTP_SERVER_REQUEST_BODY_SIZE,
+        description: "Size of HTTP server request bodies",
+        unit: "By"
+    },
+    HttpServerResponseBodySize {
+        name: HTTP_SERVER_RESPONSE_BODY_SIZE,
+        description: "Size of HTTP server response bodies",
+        unit: "By"
+    },
+    #[deprecated(note = "not recorded by the crate; use `HttpServerRequestDuration`, whose count is the number of requests")]
     HttpRequestsTotal {
         name: "http_requests_total",
         description: "Total number of HTTP requests",
         unit: "requests"
     },
+    #[deprecated(note = "not recorded by the crate; use `HttpServerRequestDuration`")]
     HttpRequestsDurationSeconds {
         name: "http_requests_duration_seconds",
         description: "Duration of HTTP requests in seconds",
         unit: "seconds"
     },
+    ProcessPanics {
+        name: "process.panics",
+        description: "Number of panics, counted by the panic hook",
+        unit: "{panic}"
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prometheus::PrometheusExporter;
+
+    fn held_back(value: &str) -> bool {
+        ENV_WARNINGS
+            .lock()
+            .unwrap()
+            .iter()
+            .flatten()
+            .any(|warning| warning.contains(&format!("{:?}", value)))
+    }
+
+    #[test]
+    fn parses_temporality_preferences() {
+        for (preference, expected) in [
+            ("cumulative", Some(Temporality::Cumulative)),
+            ("Delta", Some(Temporality::Delta)),
+            (" lowmemory ", Some(Temporality::LowMemory)),
+            ("low_memory", None),
+            ("", None),
+        ] {
+            assert_eq!(parse_temporality(preference), expected, "{:?}", preference);
+        }
+        assert!(held_back("low_memory"));
+    }
+
+    #[test]
+    fn parses_export_millis() {
+        assert_eq!(
+            parse_millis("OTEL_METRIC_EXPORT_INTERVAL", " 1500 "),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(parse_millis("OTEL_METRIC_EXPORT_INTERVAL", "5s"), None);
+        assert!(held_back("5s"));
+    }
+
+    #[test]
+    fn parses_metrics_exporters() {
+        for (value, expected) in [
+            ("otlp", vec![MetricsExporter::Otlp]),
+            (
+                " otlp , prometheus ",
+                vec![MetricsExporter::Otlp, MetricsExporter::Prometheus],
+            ),
+            ("none", vec![]),
+            ("", vec![]),
+            ("zipkin,prometheus", vec![MetricsExporter::Prometheus]),
+        ] {
+            assert_eq!(parse_metrics_exporters(value), expected, "{:?}", value);
+        }
+        assert!(held_back("zipkin"));
+    }
+
+    #[test]
+    fn maps_the_preference_per_instrument_kind() {
+        use InstrumentKind::*;
+        let kinds = [
+            Counter,
+            UpDownCounter,
+            Histogram,
+            Gauge,
+            ObservableCounter,
+            ObservableUpDownCounter,
+            ObservableGauge,
+        ];
+        let expected = [
+            (Temporality::Cumulative, [Temporality::Cumulative; 7]),
+            (
+                Temporality::Delta,
+                [
+                    Temporality::Delta,
+                    Temporality::Cumulative,
+                    Temporality::Delta,
+                    Temporality::Delta,
+                    Temporality::Delta,
+                    Temporality::Cumulative,
+                    Temporality::Delta,
+                ],
+            ),
+            (
+                Temporality::LowMemory,
+                [
+                    Temporality::Delta,
+                    Temporality::Cumulative,
+                    Temporality::Delta,
+                    Temporality::Cumulative,
+                    Temporality::Cumulative,
+                    Temporality::Cumulative,
+                    Temporality::Cumulative,
+                ],
+            ),
+        ];
+        for (preference, temporalities) in expected {
+            let config = MeterConfig::default().with_temporality(preference);
+            for (kind, temporality) in kinds.into_iter().zip(temporalities) {
+                assert_eq!(
+                    config.temporality(kind),
+                    temporality,
+                    "{:?} {:?}",
+                    preference,
+                    kind
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn instrument_overrides_take_precedence_in_the_reader() {
+        let config = MeterConfig::default()
+            .with_temporality(Temporality::Delta)
+            .with_instrument_temporality(InstrumentKind::Histogram, Temporality::LowMemory)
+            .with_instrument_temporality(InstrumentKind::Histogram, Temporality::Cumulative)
+            .with_instrument_temporality(InstrumentKind::UpDownCounter, Temporality::Delta);
+        let reader = ConfiguredTemporalityReader {
+            reader: PrometheusExporter::new(),
+            config,
+        };
+        assert_eq!(
+            reader.temporality(InstrumentKind::Histogram),
+            Temporality::Cumulative
+        );
+        assert_eq!(
+            reader.temporality(InstrumentKind::UpDownCounter),
+            Temporality::Delta
+        );
+        assert_eq!(
+            reader.temporality(InstrumentKind::Counter),
+            Temporality::Delta
+        );
+    }
 }
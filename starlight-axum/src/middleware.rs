@@ -1,3 +1,4 @@
+use crate::http_metrics::HttpMetricsLayer;
 use crate::meter::GLOBAL_METER;
 use axum::body::Bytes;
 use axum::extract::Request;
@@ -14,8 +15,7 @@ use tower_http::normalize_path::NormalizePathLayer;
 use tower_http::request_id::{
     MakeRequestUuid, PropagateRequestId, PropagateRequestIdLayer, SetRequestId, SetRequestIdLayer,
 };
-use tower_http::trace::{DefaultMakeSpan, HttpMakeClassifier, TraceLayer};
-use tower_otel_http_metrics::HTTPMetricsLayer;
+use tower_http::trace::{HttpMakeClassifier, TraceLayer};
 use tracing::Span;
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
@@ -40,13 +40,11 @@ pub fn common_middleware() -> SetRequestId<
     generate_request_id_middleware().service(trim_slash_path())
 }
 
-pub fn oltp_middleware() -> HTTPMetricsLayer {
-    tower_otel_http_metrics::HTTPMetricsLayerBuilder::builder()
-        .with_meter(GLOBAL_METER.clone())
-        .build()
-        .expect("Failed to build HTTP metrics layer")
+pub fn oltp_middleware() -> HttpMetricsLayer {
+    HttpMetricsLayer::new(&GLOBAL_METER)
 }
 
+#[allow(clippy::type_complexity)]
 pub fn trace_middleware() -> TraceLayer<
     HttpMakeClassifier,
     impl Fn(&Request<axum::body::Body>) -> Span + Clone,
@@ -66,11 +64,11 @@ pub fn trace_middleware() -> TraceLayer<
         })
         .on_request(|request: &Request<_>, span: &Span| {
             let headers = format!("{:?}", request.headers());
-            span.record("http.headers", &tracing::field::display(headers));
+            span.record("http.headers", tracing::field::display(headers));
         })
         .on_response(|response: &Response<_>, latency: Duration, span: &Span| {
-            span.record("http.status_code", &tracing::field::display(response.status()), );
-            span.record("latency", &tracing::field::display(format!("{:?}", latency)), );
+            span.record("http.status_code", tracing::field::display(response.status()), );
+            span.record("latency", tracing::field::display(format!("{:?}", latency)), );
         })
         .on_body_chunk(|_chunk: &Bytes, _latency: Duration, _span: &Span| {
             // optional body logging
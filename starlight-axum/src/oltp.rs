@@ -1,6 +1,16 @@
-use crate::logger::{CustomLogFormatter, get_logger_provider, get_or_init_logger_provider};
-use crate::meter::{get_meter_provider, get_or_init_meter_provider};
-use crate::tracer::{get_or_init_tracer_provider, get_tracer_provider};
+use crate::log_level::init_log_level;
+use crate::logger::{
+    LogFormat, get_logger_provider, get_or_init_logger_provider, get_or_init_noop_logger_provider,
+    try_get_logger_provider,
+};
+use crate::meter::{
+    emit_env_warnings, get_meter_provider, get_or_init_meter_provider,
+    get_or_init_noop_meter_provider, try_get_meter_provider,
+};
+use crate::tracer::{
+    get_or_init_noop_tracer_provider, get_or_init_tracer_provider, get_tracer_provider,
+    try_get_tracer_provider,
+};
 use opentelemetry::global;
 use opentelemetry::trace::TracerProvider;
 use opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge;
@@ -8,49 +18,273 @@ use opentelemetry_sdk::propagation::TraceContextPropagator;
 use std::error::Error;
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_opentelemetry::{MetricsLayer, OpenTelemetryLayer};
-use tracing_subscriber::EnvFilter;
-use tracing_subscriber::layer::SubscriberExt;
+use std::fmt;
+use tracing_subscriber::layer::{Layer, Layered, SubscriberExt};
+use tracing_subscriber::{EnvFilter, Registry, reload};
 use tracing_subscriber::util::SubscriberInitExt;
-use crate::{get_env_or_default, get_env_or_panic};
+use crate::get_env_or_default;
+use crate::resource::get_service_identity;
 
+/// The subscriber that custom layers are added to: the registry with the global log filter.
+pub type TelemetrySubscriber = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+
+type BoxedLayer = Box<dyn Layer<TelemetrySubscriber> + Send + Sync + 'static>;
+
+/// Settings for [`config_oltp_with`]. Each signal and log sink can be turned off on its own;
+/// a signal that is off gets a provider without exporters, so `GLOBAL_METER`, the metric macros
+/// and the middleware keep working without a collector.
+pub struct TelemetryConfig {
+    oltp_grpc_url: Option<String>,
+    traces: bool,
+    metrics: bool,
+    otlp_logs: bool,
+    console_logs: bool,
+    file_logs: bool,
+    console_format: LogFormat,
+    file_format: LogFormat,
+    console_filter: Option<String>,
+    file_filter: Option<String>,
+    otlp_log_filter: Option<String>,
+    layers: Vec<BoxedLayer>,
+}
+
+impl fmt::Debug for TelemetryConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TelemetryConfig")
+            .field("oltp_grpc_url", &self.oltp_grpc_url)
+            .field("traces", &self.traces)
+            .field("metrics", &self.metrics)
+            .field("otlp_logs", &self.otlp_logs)
+            .field("console_logs", &self.console_logs)
+            .field("file_logs", &self.file_logs)
+            .field("console_format", &self.console_format)
+            .field("file_format", &self.file_format)
+            .field("console_filter", &self.console_filter)
+            .field("file_filter", &self.file_filter)
+            .field("otlp_log_filter", &self.otlp_log_filter)
+            .field("layers", &self.layers.len())
+            .finish()
+    }
+}
+
+/// Console logs only, e.g. for local development and CLI tools.
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            oltp_grpc_url: None,
+            traces: false,
+            metrics: false,
+            otlp_logs: false,
+            console_logs: true,
+            file_logs: false,
+            console_format: LogFormat::default(),
+            file_format: LogFormat::default(),
+            console_filter: None,
+            file_filter: None,
+            otlp_log_filter: None,
+            layers: vec![],
+        }
+    }
+}
+
+impl TelemetryConfig {
+    /// Everything on: traces, metrics and logs exported to `oltp_grpc_url`, plus console and
+    /// file logs.
+    pub fn new(oltp_grpc_url: impl Into<String>) -> Self {
+        TelemetryConfig {
+            oltp_grpc_url: Some(oltp_grpc_url.into()),
+            traces: true,
+            metrics: true,
+            otlp_logs: true,
+            console_logs: true,
+            file_logs: true,
+            console_format: LogFormat::default(),
+            file_format: LogFormat::default(),
+            console_filter: None,
+            file_filter: None,
+            otlp_log_filter: None,
+            layers: vec![],
+        }
+    }
+
+    /// Endpoint of the OTLP exporters. Without one, `OTEL_EXPORTER_OTLP_ENDPOINT` is used, and
+    /// `http://localhost:4317` if that is not set either.
+    pub fn with_oltp_grpc_url(mut self, oltp_grpc_url: impl Into<String>) -> Self {
+        self.oltp_grpc_url = Some(oltp_grpc_url.into());
+        self
+    }
+
+    /// Exports spans over OTLP. With traces off, spans still get trace and span ids, for log
+    /// lines and propagation, but are not exported.
+    pub fn with_traces(mut self, enabled: bool) -> Self {
+        self.traces = enabled;
+        self
+    }
+
+    /// Exports metrics through the exporters in `OTEL_METRICS_EXPORTER`.
+    pub fn with_metrics(mut self, enabled: bool) -> Self {
+        self.metrics = enabled;
+        self
+    }
+
+    /// Exports log events over OTLP.
+    pub fn with_otlp_logs(mut self, enabled: bool) -> Self {
+        self.otlp_logs = enabled;
+        self
+    }
+
+    /// Writes logs to stdout.
+    pub fn with_console_logs(mut self, enabled: bool) -> Self {
+        self.console_logs = enabled;
+        self
+    }
+
+    /// Writes logs to rolling files under `.logs`.
+    pub fn with_file_logs(mut self, enabled: bool) -> Self {
+        self.file_logs = enabled;
+        self
+    }
+
+    /// Format of the logs written to stdout.
+    pub fn with_console_format(mut self, format: LogFormat) -> Self {
+        self.console_format = format;
+        self
+    }
+
+    /// Format of the logs written to the rolling files under `.logs`.
+    pub fn with_file_format(mut self, format: LogFormat) -> Self {
+        self.file_format = format;
+        self
+    }
+
+    /// `EnvFilter` directives for stdout only, e.g. `info`. Each sink sees at most what the global
+    /// filter (`RUST_LOG`, see `get_log_level_handle`) lets through.
+    pub fn with_console_filter(mut self, directives: impl Into<String>) -> Self {
+        self.console_filter = Some(directives.into());
+        self
+    }
+
+    /// `EnvFilter` directives for the log files only.
+    pub fn with_file_filter(mut self, directives: impl Into<String>) -> Self {
+        self.file_filter = Some(directives.into());
+        self
+    }
+
+    /// `EnvFilter` directives for the logs exported over OTLP only.
+    pub fn with_otlp_log_filter(mut self, directives: impl Into<String>) -> Self {
+        self.otlp_log_filter = Some(directives.into());
+        self
+    }
+
+    /// Adds a layer of your own to the subscriber, after the global filter.
+    pub fn with_layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<TelemetrySubscriber> + Send + Sync + 'static,
+    {
+        self.layers.push(Box::new(layer));
+        self
+    }
+}
+
+fn sink_filter(
+    sink: &str,
+    directives: Option<&str>,
+) -> Result<Option<EnvFilter>, Box<dyn Error + Send + Sync + 'static>> {
+    directives
+        .map(|directives| {
+            EnvFilter::try_new(directives)
+                .map_err(|err| format!("invalid {} log filter {:?}: {}", sink, directives, err).into())
+        })
+        .transpose()
+}
+
+/// Installs the tracer, logger and meter providers and the global subscriber. The service is
+/// named after `init_service_identity` if called before, and the environment otherwise.
 pub fn config_oltp(
     oltp_grpc_url: &str,
 ) -> Result<WorkerGuard, Box<dyn Error + Send + Sync + 'static>> {
-    let tracer_provider = get_or_init_tracer_provider(oltp_grpc_url);
-    let logger_provider = get_or_init_logger_provider(oltp_grpc_url);
-    let meter_provider = get_or_init_meter_provider(oltp_grpc_url);
+    let guard = config_oltp_with(TelemetryConfig::new(oltp_grpc_url))?;
+    Ok(guard.expect("file logs are on in TelemetryConfig::new"))
+}
+
+/// Like [`config_oltp`], with explicit settings. Returns the guard of the file writer if file
+/// logs are on; keep it alive until the end of `main` so buffered lines get written.
+pub fn config_oltp_with(
+    config: TelemetryConfig,
+) -> Result<Option<WorkerGuard>, Box<dyn Error + Send + Sync + 'static>> {
+    let oltp_grpc_url = config.oltp_grpc_url.unwrap_or_else(|| {
+        get_env_or_default("OTEL_EXPORTER_OTLP_ENDPOINT", "http://localhost:4317".to_owned())
+    });
+    let console_filter = sink_filter("console", config.console_filter.as_deref())?;
+    let file_filter = sink_filter("file", config.file_filter.as_deref())?;
+    let otlp_log_filter = sink_filter("OTLP", config.otlp_log_filter.as_deref())?;
+    let tracer_provider = if config.traces {
+        get_or_init_tracer_provider(&oltp_grpc_url)
+    } else {
+        get_or_init_noop_tracer_provider()
+    };
+    let logger_provider = if config.otlp_logs {
+        get_or_init_logger_provider(&oltp_grpc_url)
+    } else {
+        get_or_init_noop_logger_provider()
+    };
+    let meter_provider = if config.metrics {
+        get_or_init_meter_provider(&oltp_grpc_url)
+    } else {
+        get_or_init_noop_meter_provider()
+    };
     global::set_tracer_provider(tracer_provider.clone());
     global::set_meter_provider(meter_provider.clone());
 
-    let tracer = tracer_provider.tracer(get_env_or_panic("CARGO_PKG_NAME"));
+    let service_name = get_service_identity().name();
+    // Installed with traces off too, so log lines keep their trace context and incoming trace
+    // context is still propagated.
+    let tracing_layer = OpenTelemetryLayer::new(tracer_provider.tracer(service_name.to_owned()));
+    let metrics_layer = config
+        .metrics
+        .then(|| MetricsLayer::new(meter_provider));
     // Create a new OpenTelemetryTracingBridge using the above LoggerProvider.
-    let layer = OpenTelemetryTracingBridge::new(&logger_provider);
+    let layer = config
+        .otlp_logs
+        .then(|| OpenTelemetryTracingBridge::new(&logger_provider).with_filter(otlp_log_filter));
 
-    let file_appender =
-        tracing_appender::rolling::minutely(".logs", get_env_or_panic("CARGO_PKG_NAME"));
-    let (nonblocking_file, _guard_file) = tracing_appender::non_blocking(file_appender);
+    let (file_logger, guard_file) = if config.file_logs {
+        let file_appender =
+            tracing_appender::rolling::minutely(".logs", service_name);
+        let (nonblocking_file, guard_file) = tracing_appender::non_blocking(file_appender);
 
-    let file_logger = tracing_subscriber::fmt::layer()
-        .event_format(CustomLogFormatter)
-        .with_writer(nonblocking_file);
+        let file_logger = tracing_subscriber::fmt::layer()
+            .event_format(config.file_format)
+            .with_writer(nonblocking_file)
+            .with_filter(file_filter);
+        (Some(file_logger), Some(guard_file))
+    } else {
+        (None, None)
+    };
 
-    let console_logger = tracing_subscriber::fmt::layer()
-        .event_format(CustomLogFormatter)
-        .with_writer(std::io::stdout);
+    let console_logger = config.console_logs.then(|| {
+        tracing_subscriber::fmt::layer()
+            .event_format(config.console_format)
+            .with_writer(std::io::stdout)
+            .with_filter(console_filter)
+    });
 
-    let log_level_filter = EnvFilter::new(get_env_or_default("RUST_LOG", "debug,axum_web_server=debug,tower_http=trace".to_owned()), );
+    // Reloadable at runtime through `get_log_level_handle`.
+    let log_level_filter = init_log_level(get_env_or_default("RUST_LOG", "debug,axum_web_server=debug,tower_http=trace".to_owned()));
 
     global::set_text_map_propagator(TraceContextPropagator::new());
     tracing_subscriber::registry()
         .with(log_level_filter)
+        .with(config.layers)
         .with(file_logger)
         .with(console_logger)
         .with(layer)
-        .with(MetricsLayer::new(meter_provider))
-        .with(OpenTelemetryLayer::new(tracer))
+        .with(metrics_layer)
+        .with(tracing_layer)
         .init();
+    emit_env_warnings();
 
-    Ok(_guard_file)
+    Ok(guard_file)
 }
 
 pub fn shutdown_oltp() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
@@ -59,3 +293,23 @@ pub fn shutdown_oltp() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
     get_logger_provider().shutdown()?;
     Ok(())
 }
+
+/// Exports what the installed providers have buffered, skipping those that are not installed.
+/// Each provider waits at most for its export timeout.
+pub fn flush_oltp() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let mut errors = vec![];
+    if let Some(provider) = try_get_tracer_provider() && let Err(err) = provider.force_flush() {
+        errors.push(format!("traces: {}", err));
+    }
+    if let Some(provider) = try_get_logger_provider() && let Err(err) = provider.force_flush() {
+        errors.push(format!("logs: {}", err));
+    }
+    if let Some(provider) = try_get_meter_provider() && let Err(err) = provider.force_flush() {
+        errors.push(format!("metrics: {}", err));
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("failed to flush {}", errors.join(", ")).into())
+    }
+}
@@ -0,0 +1,186 @@
+use crate::meter::{GLOBAL_METER, Metric};
+use crate::oltp::flush_oltp;
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::panic::PanicHookInfo;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// How long the hook waits for the providers to export before letting the process die.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Wraps the panic hook with one that logs each panic as an ERROR event through the installed
+/// subscriber, so it carries the trace context of the current span and reaches the collector.
+/// The event has the message, location, thread and a backtrace. The hook also counts the panic
+/// in `process.panics`, then hands over to the previous hook, which prints to stderr.
+///
+/// Panics the process survives, such as those of tokio tasks, do not wait for anything. When
+/// the process is about to end, with `panic = "abort"` or on the main thread, the providers are
+/// flushed on a separate thread first, waiting at most two seconds. The hook cannot tell whether
+/// a main thread panic will be caught, so one inside `catch_unwind` flushes as well; keep
+/// `catch_unwind` on the main thread off hot paths.
+///
+/// Call it after `config_oltp`. Until a subscriber is installed, panics only go to the previous
+/// hook.
+pub fn install_panic_hook() {
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if tracing::dispatcher::has_been_set() {
+            log_panic(info);
+            count_panic();
+            if is_fatal() {
+                flush_with_timeout();
+            }
+        }
+        previous_hook(info);
+    }));
+}
+
+/// Whether the process ends with this panic: it aborts, or unwinds out of `main`. Any main thread
+/// panic counts, since a surrounding `catch_unwind` is invisible to the hook.
+fn is_fatal() -> bool {
+    cfg!(panic = "abort") || std::thread::current().name() == Some("main")
+}
+
+/// Flushes on another thread, so that a provider stuck on an export cannot hold up the
+/// panicking one for longer than `FLUSH_TIMEOUT`.
+fn flush_with_timeout() {
+    let (done, flushed) = mpsc::channel();
+    let spawned = std::thread::Builder::new()
+        .name("panic-flush".to_owned())
+        .spawn(move || {
+            let _ = done.send(flush_oltp());
+        });
+    if spawned.is_err() {
+        return;
+    }
+    match flushed.recv_timeout(FLUSH_TIMEOUT) {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => eprintln!("failed to flush telemetry after a panic: {}", err),
+        Err(_) => eprintln!("timed out flushing telemetry after a panic"),
+    }
+}
+
+fn log_panic(info: &PanicHookInfo<'_>) {
+    let message = payload_message(info.payload());
+    let thread = std::thread::current();
+    let thread_name = match thread.name() {
+        Some(name) => name.to_owned(),
+        None => format!("{:?}", thread.id()),
+    };
+    // Captured regardless of RUST_BACKTRACE; a panic is rare enough to afford it.
+    let backtrace = Backtrace::force_capture();
+
+    match info.location() {
+        Some(location) => error!(
+            exception.r#type = "panic",
+            exception.message = message,
+            exception.stacktrace = %backtrace,
+            thread.name = thread_name,
+            code.filepath = location.file(),
+            code.lineno = location.line(),
+            code.column = location.column(),
+            "thread '{}' panicked at {}: {}",
+            thread_name,
+            location,
+            message,
+        ),
+        None => error!(
+            exception.r#type = "panic",
+            exception.message = message,
+            exception.stacktrace = %backtrace,
+            thread.name = thread_name,
+            "thread '{}' panicked: {}",
+            thread_name,
+            message,
+        ),
+    }
+}
+
+fn count_panic() {
+    let panics = Metric::ProcessPanics;
+    GLOBAL_METER
+        .u64_counter(panics.name())
+        .with_description(panics.description())
+        .with_unit(panics.unit())
+        .build()
+        .add(1, &[]);
+}
+
+/// The message of `panic!`, which is a `&str` or a `String` unless raised with `panic_any`.
+fn payload_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logfmt::LogfmtFormatter;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn logs_the_panic_and_calls_the_previous_hook() {
+        let thread_name = std::thread::current().name().unwrap().to_owned();
+        // Other tests may panic while the hooks are installed, so only count this thread.
+        let previous_calls = Arc::new(Mutex::new(vec![]));
+        let calls = previous_calls.clone();
+        std::panic::set_hook(Box::new(move |info| {
+            let thread = std::thread::current();
+            if thread.name() == Some(thread_name.as_str()) {
+                calls
+                    .lock()
+                    .unwrap()
+                    .push(payload_message(info.payload()).to_owned());
+            }
+        }));
+        install_panic_hook();
+
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .event_format(LogfmtFormatter::new())
+            .with_writer(move || writer.clone())
+            .finish();
+        let line = line!() + 2;
+        let result = tracing::subscriber::with_default(subscriber, || {
+            std::panic::catch_unwind(|| panic!("boom {}", 42))
+        });
+        // Restores the default hook.
+        drop(std::panic::take_hook());
+
+        assert!(result.is_err());
+        assert_eq!(*previous_calls.lock().unwrap(), ["boom 42"]);
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let thread = std::thread::current();
+        let fields = [
+            " level=error ".to_owned(),
+            " exception.message=\"boom 42\"".to_owned(),
+            format!(" code.lineno={}", line),
+            format!(" thread.name={} ", thread.name().unwrap()),
+        ];
+        for field in fields {
+            assert!(output.contains(&field), "{} in {}", field, output);
+        }
+    }
+}
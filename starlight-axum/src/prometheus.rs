@@ -0,0 +1,684 @@
+use crate::exemplar::{DEFAULT_MAX_AGE, SampledExemplar, bucket_exemplars, exemplars_for};
+use axum::Router;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use opentelemetry::trace::{SpanId, TraceId};
+use opentelemetry::{InstrumentationScope, KeyValue};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::metrics::data::{
+    Gauge, Histogram, HistogramDataPoint, Metric, ResourceMetrics, Sum,
+};
+use opentelemetry_sdk::metrics::reader::MetricReader;
+use opentelemetry_sdk::metrics::{InstrumentKind, ManualReader, MetricResult, Pipeline, Temporality};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, OnceLock, Weak};
+use std::time::UNIX_EPOCH;
+
+pub const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Text exposition format served by the scrape endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpositionFormat {
+    Prometheus,
+    OpenMetrics,
+}
+
+impl ExpositionFormat {
+    /// Picks OpenMetrics when the scraper advertises it in `Accept`, Prometheus text otherwise.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let accepts_openmetrics = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|media_type| {
+                media_type
+                    .trim()
+                    .starts_with("application/openmetrics-text")
+            });
+
+        if accepts_openmetrics {
+            ExpositionFormat::OpenMetrics
+        } else {
+            ExpositionFormat::Prometheus
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExpositionFormat::Prometheus => PROMETHEUS_CONTENT_TYPE,
+            ExpositionFormat::OpenMetrics => OPENMETRICS_CONTENT_TYPE,
+        }
+    }
+}
+
+/// Pull-based metric reader that renders the meter provider's state on every scrape.
+#[derive(Debug, Clone)]
+pub struct PrometheusExporter {
+    reader: Arc<ManualReader>,
+}
+
+impl Default for PrometheusExporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PrometheusExporter {
+    pub fn new() -> Self {
+        PrometheusExporter {
+            reader: Arc::new(
+                ManualReader::builder()
+                    .with_temporality(Temporality::Cumulative)
+                    .build(),
+            ),
+        }
+    }
+
+    pub fn encode(&self, format: ExpositionFormat) -> MetricResult<String> {
+        let mut resource_metrics = ResourceMetrics {
+            resource: Resource::builder_empty().build(),
+            scope_metrics: vec![],
+        };
+        self.reader.collect(&mut resource_metrics)?;
+        Ok(encode_resource_metrics(&resource_metrics, format))
+    }
+}
+
+impl MetricReader for PrometheusExporter {
+    fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+        self.reader.register_pipeline(pipeline)
+    }
+
+    fn collect(&self, rm: &mut ResourceMetrics) -> MetricResult<()> {
+        self.reader.collect(rm)
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.reader.force_flush()
+    }
+
+    fn shutdown(&self) -> OTelSdkResult {
+        self.reader.shutdown()
+    }
+
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.reader.temporality(kind)
+    }
+}
+
+static PROMETHEUS_EXPORTER: OnceLock<PrometheusExporter> = OnceLock::new();
+
+pub fn get_prometheus_exporter() -> &'static PrometheusExporter {
+    PROMETHEUS_EXPORTER
+        .get()
+        .expect("failed to get prometheus exporter")
+}
+
+pub fn get_or_init_prometheus_exporter() -> PrometheusExporter {
+    PROMETHEUS_EXPORTER
+        .get_or_init(PrometheusExporter::new)
+        .clone()
+}
+
+/// Router exposing `GET /metrics`, to be merged into the service's own router.
+pub fn prometheus_router() -> Router {
+    Router::new().route("/metrics", get(metrics_handler))
+}
+
+pub async fn metrics_handler(headers: HeaderMap) -> Response {
+    let Some(exporter) = PROMETHEUS_EXPORTER.get() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "prometheus exporter is not enabled",
+        )
+            .into_response();
+    };
+
+    let format = ExpositionFormat::from_headers(&headers);
+    match exporter.encode(format) {
+        Ok(body) => (
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(format.content_type()),
+            )],
+            body,
+        )
+            .into_response(),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("failed to collect metrics: {}", err),
+        )
+            .into_response(),
+    }
+}
+
+struct MetricFamily {
+    name: String,
+    kind: &'static str,
+    help: String,
+    unit: Option<&'static str>,
+    samples: String,
+}
+
+struct FamilySet {
+    format: ExpositionFormat,
+    families: Vec<MetricFamily>,
+    index: HashMap<String, usize>,
+}
+
+impl FamilySet {
+    fn family(
+        &mut self,
+        name: &str,
+        kind: &'static str,
+        help: &str,
+        unit: Option<&'static str>,
+    ) -> &mut MetricFamily {
+        let position = match self.index.get(name) {
+            Some(position) => *position,
+            None => {
+                self.families.push(MetricFamily {
+                    name: name.to_owned(),
+                    kind,
+                    help: help.to_owned(),
+                    unit,
+                    samples: String::new(),
+                });
+                self.index.insert(name.to_owned(), self.families.len() - 1);
+                self.families.len() - 1
+            }
+        };
+        &mut self.families[position]
+    }
+}
+
+pub fn encode_resource_metrics(metrics: &ResourceMetrics, format: ExpositionFormat) -> String {
+    let mut set = FamilySet {
+        format,
+        families: vec![],
+        index: HashMap::new(),
+    };
+
+    for scope_metrics in &metrics.scope_metrics {
+        let scope_labels = scope_labels(&scope_metrics.scope);
+        for metric in &scope_metrics.metrics {
+            encode_metric(&mut set, metric, &scope_labels);
+        }
+    }
+
+    let mut output = String::new();
+    encode_target_info(&mut output, &metrics.resource, format);
+    for family in &set.families {
+        if family.samples.is_empty() {
+            continue;
+        }
+        let family_name = match (format, family.kind) {
+            (ExpositionFormat::Prometheus, "counter") => format!("{}_total", family.name),
+            _ => family.name.clone(),
+        };
+        if !family.help.is_empty() {
+            let _ = writeln!(output, "# HELP {} {}", family_name, escape_help(&family.help));
+        }
+        let _ = writeln!(output, "# TYPE {} {}", family_name, family.kind);
+        if let (ExpositionFormat::OpenMetrics, Some(unit)) = (format, family.unit) {
+            let _ = writeln!(output, "# UNIT {} {}", family_name, unit);
+        }
+        output.push_str(&family.samples);
+    }
+    if format == ExpositionFormat::OpenMetrics {
+        output.push_str("# EOF\n");
+    }
+    output
+}
+
+/// Exposes resource attributes once as `target_info`, instead of repeating them on every series.
+fn encode_target_info(output: &mut String, resource: &Resource, format: ExpositionFormat) {
+    if resource.is_empty() {
+        return;
+    }
+    let (family_name, kind) = match format {
+        ExpositionFormat::Prometheus => ("target_info", "gauge"),
+        ExpositionFormat::OpenMetrics => ("target", "info"),
+    };
+    let labels = resource
+        .iter()
+        .map(|(key, value)| (sanitize_label_name(key.as_str()), value.to_string()))
+        .collect::<Vec<_>>();
+    let _ = writeln!(output, "# HELP {} Target metadata", family_name);
+    let _ = writeln!(output, "# TYPE {} {}", family_name, kind);
+    write_sample(output, "target_info", &labels, "1");
+}
+
+fn encode_metric(set: &mut FamilySet, metric: &Metric, scope_labels: &[(String, String)]) {
+    let data = metric.data.as_any();
+    if let Some(sum) = data.downcast_ref::<Sum<f64>>() {
+        encode_sum(set, metric, scope_labels, sum);
+    } else if let Some(sum) = data.downcast_ref::<Sum<u64>>() {
+        encode_sum(set, metric, scope_labels, sum);
+    } else if let Some(sum) = data.downcast_ref::<Sum<i64>>() {
+        encode_sum(set, metric, scope_labels, sum);
+    } else if let Some(gauge) = data.downcast_ref::<Gauge<f64>>() {
+        encode_gauge(set, metric, scope_labels, gauge);
+    } else if let Some(gauge) = data.downcast_ref::<Gauge<u64>>() {
+        encode_gauge(set, metric, scope_labels, gauge);
+    } else if let Some(gauge) = data.downcast_ref::<Gauge<i64>>() {
+        encode_gauge(set, metric, scope_labels, gauge);
+    } else if let Some(histogram) = data.downcast_ref::<Histogram<f64>>() {
+        encode_histogram(set, metric, scope_labels, histogram);
+    } else if let Some(histogram) = data.downcast_ref::<Histogram<u64>>() {
+        encode_histogram(set, metric, scope_labels, histogram);
+    } else if let Some(histogram) = data.downcast_ref::<Histogram<i64>>() {
+        encode_histogram(set, metric, scope_labels, histogram);
+    }
+}
+
+fn encode_sum<T: SampleValue>(
+    set: &mut FamilySet,
+    metric: &Metric,
+    scope_labels: &[(String, String)],
+    sum: &Sum<T>,
+) {
+    let unit = unit_suffix(&metric.unit, sum.is_monotonic);
+    if sum.is_monotonic {
+        let name = metric_name(&metric.name, unit, Some("_total"));
+        let family = set.family(&name, "counter", &metric.description, unit);
+        let sample_name = format!("{}_total", name);
+        for data_point in &sum.data_points {
+            let labels = labels(scope_labels, &data_point.attributes);
+            write_sample(&mut family.samples, &sample_name, &labels, &data_point.value.render());
+        }
+    } else {
+        let name = metric_name(&metric.name, unit, None);
+        let family = set.family(&name, "gauge", &metric.description, unit);
+        for data_point in &sum.data_points {
+            let labels = labels(scope_labels, &data_point.attributes);
+            write_sample(&mut family.samples, &name, &labels, &data_point.value.render());
+        }
+    }
+}
+
+fn encode_gauge<T: SampleValue>(
+    set: &mut FamilySet,
+    metric: &Metric,
+    scope_labels: &[(String, String)],
+    gauge: &Gauge<T>,
+) {
+    let unit = unit_suffix(&metric.unit, false);
+    let name = metric_name(&metric.name, unit, None);
+    let family = set.family(&name, "gauge", &metric.description, unit);
+    for data_point in &gauge.data_points {
+        let labels = labels(scope_labels, &data_point.attributes);
+        write_sample(&mut family.samples, &name, &labels, &data_point.value.render());
+    }
+}
+
+fn encode_histogram<T: SampleValue>(
+    set: &mut FamilySet,
+    metric: &Metric,
+    scope_labels: &[(String, String)],
+    histogram: &Histogram<T>,
+) {
+    let unit = unit_suffix(&metric.unit, false);
+    let name = metric_name(&metric.name, unit, None);
+    // Exemplars only exist in the OpenMetrics format.
+    let with_exemplars = set.format == ExpositionFormat::OpenMetrics;
+    let family = set.family(&name, "histogram", &metric.description, unit);
+    for data_point in &histogram.data_points {
+        let exemplars = if with_exemplars {
+            // The scrape interval is unknown, so exemplars are served for a typical one.
+            let exemplars = exemplars_for(
+                &metric.name,
+                &data_point.attributes,
+                histogram.start_time,
+                histogram.time,
+                DEFAULT_MAX_AGE,
+            );
+            bucket_exemplars(&exemplars, &data_point.bounds)
+        } else {
+            vec![]
+        };
+        encode_histogram_data_point(&mut family.samples, &name, scope_labels, data_point, &exemplars);
+    }
+}
+
+fn encode_histogram_data_point<T: SampleValue>(
+    output: &mut String,
+    name: &str,
+    scope_labels: &[(String, String)],
+    data_point: &HistogramDataPoint<T>,
+    exemplars: &[Option<SampledExemplar>],
+) {
+    let labels = labels(scope_labels, &data_point.attributes);
+    let bucket_name = format!("{}_bucket", name);
+
+    let mut cumulative = 0;
+    for (position, count) in data_point.bucket_counts.iter().enumerate() {
+        cumulative += count;
+        let le = data_point
+            .bounds
+            .get(position)
+            .map(|bound| bound.render())
+            .unwrap_or_else(|| "+Inf".to_owned());
+        let mut bucket_labels = labels.clone();
+        bucket_labels.push(("le".to_owned(), le));
+        let exemplar = exemplars.get(position).copied().flatten();
+        write_sample_with_exemplar(
+            output,
+            &bucket_name,
+            &bucket_labels,
+            &cumulative.to_string(),
+            exemplar,
+        );
+    }
+    write_sample(output, &format!("{}_sum", name), &labels, &data_point.sum.render());
+    write_sample(
+        output,
+        &format!("{}_count", name),
+        &labels,
+        &data_point.count.to_string(),
+    );
+}
+
+trait SampleValue: Copy {
+    fn render(&self) -> String;
+}
+
+impl SampleValue for f64 {
+    fn render(&self) -> String {
+        if self.is_nan() {
+            "NaN".to_owned()
+        } else if self.is_infinite() && self.is_sign_positive() {
+            "+Inf".to_owned()
+        } else if self.is_infinite() {
+            "-Inf".to_owned()
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+impl SampleValue for u64 {
+    fn render(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl SampleValue for i64 {
+    fn render(&self) -> String {
+        self.to_string()
+    }
+}
+
+fn scope_labels(scope: &InstrumentationScope) -> Vec<(String, String)> {
+    let mut labels = vec![("otel_scope_name".to_owned(), scope.name().to_owned())];
+    if let Some(version) = scope.version() {
+        labels.push(("otel_scope_version".to_owned(), version.to_owned()));
+    }
+    labels
+}
+
+fn labels(scope_labels: &[(String, String)], attributes: &[KeyValue]) -> Vec<(String, String)> {
+    let mut labels = scope_labels.to_vec();
+    labels.extend(
+        attributes
+            .iter()
+            .map(|kv| (sanitize_label_name(kv.key.as_str()), kv.value.to_string())),
+    );
+    labels
+}
+
+fn write_sample(output: &mut String, name: &str, labels: &[(String, String)], value: &str) {
+    write_sample_with_exemplar(output, name, labels, value, None);
+}
+
+fn write_sample_with_exemplar(
+    output: &mut String,
+    name: &str,
+    labels: &[(String, String)],
+    value: &str,
+    exemplar: Option<SampledExemplar>,
+) {
+    output.push_str(name);
+    if !labels.is_empty() {
+        output.push('{');
+        for (position, (key, value)) in labels.iter().enumerate() {
+            if position > 0 {
+                output.push(',');
+            }
+            let _ = write!(output, "{}=\"{}\"", key, escape_label_value(value));
+        }
+        output.push('}');
+    }
+    let _ = write!(output, " {}", value);
+    if let Some(exemplar) = exemplar {
+        let timestamp = exemplar
+            .time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let _ = write!(
+            output,
+            " # {{trace_id=\"{}\",span_id=\"{}\"}} {} {}",
+            TraceId::from_bytes(exemplar.trace_id),
+            SpanId::from_bytes(exemplar.span_id),
+            exemplar.value.render(),
+            timestamp
+        );
+    }
+    output.push('\n');
+}
+
+/// Maps OpenTelemetry units to the Prometheus base-unit suffix, dropping annotations like `{request}`.
+fn unit_suffix(unit: &str, is_counter: bool) -> Option<&'static str> {
+    match unit {
+        "s" | "seconds" => Some("seconds"),
+        "ms" | "milliseconds" => Some("milliseconds"),
+        "By" | "bytes" => Some("bytes"),
+        "1" if !is_counter => Some("ratio"),
+        _ => None,
+    }
+}
+
+fn metric_name(name: &str, unit: Option<&str>, strip: Option<&str>) -> String {
+    let mut name = sanitize_metric_name(name);
+    if let Some(suffix) = strip
+        && let Some(stripped) = name.strip_suffix(suffix)
+    {
+        name = stripped.to_owned();
+    }
+    if let Some(unit) = unit
+        && !name.ends_with(unit)
+    {
+        name.push('_');
+        name.push_str(unit);
+    }
+    name
+}
+
+fn sanitize_metric_name(name: &str) -> String {
+    sanitize(name, |c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+fn sanitize_label_name(name: &str) -> String {
+    sanitize(name, |c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn sanitize(name: &str, is_valid: impl Fn(char) -> bool) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if is_valid(c) { c } else { '_' })
+        .collect();
+    if sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exemplar::record_exemplar;
+    use opentelemetry::metrics::MeterProvider;
+    use opentelemetry::trace::{TraceContextExt, TracerProvider};
+    use opentelemetry_sdk::metrics::SdkMeterProvider;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    const SCOPE: &str = "otel_scope_name=\"test\"";
+
+    /// Records a counter and a histogram on a provider of its own, which has to outlive the
+    /// scrape, also returning the trace id of the exemplar recorded with the histogram's `0.5`.
+    fn record(histogram_name: &'static str) -> (PrometheusExporter, SdkMeterProvider, TraceId) {
+        let exporter = PrometheusExporter::new();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(exporter.clone())
+            .with_resource(
+                Resource::builder_empty()
+                    .with_attribute(KeyValue::new("service.name", "api"))
+                    .build(),
+            )
+            .build();
+        let meter = provider.meter("test");
+
+        let requests = meter
+            .u64_counter("requests")
+            .with_description("Handled requests")
+            .with_unit("{request}")
+            .build();
+        let attributes = [KeyValue::new("http.method", "GET")];
+        requests.add(3, &attributes);
+
+        let latency = meter
+            .f64_histogram(histogram_name)
+            .with_description("Request latency")
+            .with_unit("s")
+            .with_boundaries(vec![0.1, 1.0])
+            .build();
+        latency.record(0.05, &attributes);
+
+        let tracer = SdkTracerProvider::builder().build().tracer("test");
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        let trace_id = tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!("request");
+            let _entered = span.enter();
+            latency.record(0.5, &attributes);
+            record_exemplar(histogram_name, 0.5, &attributes);
+            span.context().span().span_context().trace_id()
+        });
+
+        (exporter, provider, trace_id)
+    }
+
+    fn exemplar_suffix(trace_id: TraceId) -> String {
+        format!(" # {{trace_id=\"{}\",", trace_id)
+    }
+
+    #[test]
+    fn encodes_prometheus_text() {
+        let (exporter, _provider, trace_id) = record("prometheus.latency");
+        let text = exporter.encode(ExpositionFormat::Prometheus).unwrap();
+
+        assert!(text.contains("# TYPE target_info gauge\ntarget_info{service_name=\"api\"} 1\n"));
+        assert!(text.contains(&format!(
+            "# HELP requests_total Handled requests\n# TYPE requests_total counter\nrequests_total{{{},http_method=\"GET\"}} 3\n",
+            SCOPE
+        )));
+        let labels = format!("{},http_method=\"GET\"", SCOPE);
+        assert!(text.contains(&format!(
+            "# TYPE prometheus_latency_seconds histogram\n\
+             prometheus_latency_seconds_bucket{{{labels},le=\"0.1\"}} 1\n\
+             prometheus_latency_seconds_bucket{{{labels},le=\"1\"}} 2\n\
+             prometheus_latency_seconds_bucket{{{labels},le=\"+Inf\"}} 2\n\
+             prometheus_latency_seconds_sum{{{labels}}} 0.55\n\
+             prometheus_latency_seconds_count{{{labels}}} 2\n"
+        )));
+        assert!(!text.contains("# UNIT"));
+        assert!(!text.contains(&exemplar_suffix(trace_id)), "{}", text);
+        assert!(!text.contains("# EOF"));
+    }
+
+    #[test]
+    fn encodes_openmetrics_text_with_exemplars() {
+        let (exporter, _provider, trace_id) = record("openmetrics.latency");
+        assert_ne!(trace_id, TraceId::INVALID);
+        let text = exporter.encode(ExpositionFormat::OpenMetrics).unwrap();
+
+        assert!(text.contains("# TYPE target info\ntarget_info{service_name=\"api\"} 1\n"));
+        assert!(text.contains(&format!(
+            "# TYPE requests counter\nrequests_total{{{},http_method=\"GET\"}} 3\n",
+            SCOPE
+        )));
+        assert!(text.contains(
+            "# TYPE openmetrics_latency_seconds histogram\n# UNIT openmetrics_latency_seconds seconds\n"
+        ));
+
+        // Only the bucket holding the exemplar's value carries it.
+        let buckets = text
+            .lines()
+            .filter(|line| line.starts_with("openmetrics_latency_seconds_bucket"))
+            .collect::<Vec<_>>();
+        assert_eq!(buckets.len(), 3);
+        assert!(!buckets[0].contains(" # "), "{}", buckets[0]);
+        assert!(
+            buckets[1].contains(&format!(
+                "le=\"1\"}} 2{}span_id=",
+                exemplar_suffix(trace_id)
+            )),
+            "{}",
+            buckets[1]
+        );
+        assert!(buckets[1].contains("\"} 0.5 "), "{}", buckets[1]);
+        assert!(!buckets[2].contains(" # "), "{}", buckets[2]);
+        assert!(text.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn picks_the_format_from_accept() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            ExpositionFormat::from_headers(&headers),
+            ExpositionFormat::Prometheus
+        );
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static(
+                "text/plain;q=0.5, application/openmetrics-text; version=1.0.0",
+            ),
+        );
+        assert_eq!(
+            ExpositionFormat::from_headers(&headers),
+            ExpositionFormat::OpenMetrics
+        );
+    }
+
+    #[test]
+    fn sanitizes_names_and_escapes_values() {
+        assert_eq!(
+            metric_name("http.server.duration", Some("seconds"), None),
+            "http_server_duration_seconds"
+        );
+        assert_eq!(metric_name("jobs_total", None, Some("_total")), "jobs");
+        assert_eq!(sanitize_label_name("1st-label"), "_1st_label");
+        assert_eq!(escape_label_value("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+        assert_eq!(escape_help("line\nbreak \\"), "line\\nbreak \\\\");
+    }
+}
@@ -1,26 +1,227 @@
 use opentelemetry::KeyValue;
 use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::resource::{
+    EnvResourceDetector, SdkProvidedResourceDetector, TelemetryResourceDetector,
+};
 use opentelemetry_semantic_conventions::attribute::{
-    DEPLOYMENT_ENVIRONMENT_NAME, SERVICE_NAME, SERVICE_VERSION,
+    DEPLOYMENT_ENVIRONMENT_NAME, SERVICE_INSTANCE_ID, SERVICE_NAME, SERVICE_NAMESPACE,
+    SERVICE_VERSION,
 };
 use std::sync::OnceLock;
-use crate::{get_env_or_default, get_env_or_panic};
+use crate::get_env_or_default;
+use crate::resource_detector::ResourceDetectors;
+
+/// Default service name from the OpenTelemetry spec, used when nothing else names the service.
+const UNKNOWN_SERVICE: &str = "unknown_service";
+
+/// Identity of the running service, reported as `service.*` resource attributes and used to
+/// name the meter scope, tracer and log files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceIdentity {
+    name: String,
+    version: Option<String>,
+    namespace: Option<String>,
+    instance_id: Option<String>,
+}
+
+impl ServiceIdentity {
+    pub fn new(name: impl Into<String>, version: impl Into<String>) -> Self {
+        ServiceIdentity {
+            name: name.into(),
+            version: Some(version.into()),
+            namespace: None,
+            instance_id: None,
+        }
+    }
+
+    /// Reads `OTEL_SERVICE_NAME` and the `service.*` keys of `OTEL_RESOURCE_ATTRIBUTES`; the name
+    /// defaults to `unknown_service`.
+    pub fn from_env() -> Self {
+        let attributes = env_resource_attributes();
+        let attribute = |key: &str| {
+            attributes
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.clone())
+        };
+
+        ServiceIdentity {
+            name: std::env::var("OTEL_SERVICE_NAME")
+                .ok()
+                .filter(|name| !name.is_empty())
+                .or_else(|| attribute(SERVICE_NAME))
+                .unwrap_or_else(|| UNKNOWN_SERVICE.to_owned()),
+            version: attribute(SERVICE_VERSION),
+            namespace: attribute(SERVICE_NAMESPACE),
+            instance_id: attribute(SERVICE_INSTANCE_ID),
+        }
+    }
+
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    pub fn with_instance_id(mut self, instance_id: impl Into<String>) -> Self {
+        self.instance_id = Some(instance_id.into());
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    pub fn instance_id(&self) -> Option<&str> {
+        self.instance_id.as_deref()
+    }
+
+    /// Fills in what was not set explicitly from the environment.
+    fn or_env(mut self) -> Self {
+        let env = ServiceIdentity::from_env();
+        self.version = self.version.or(env.version);
+        self.namespace = self.namespace.or(env.namespace);
+        self.instance_id = self.instance_id.or(env.instance_id);
+        self
+    }
+}
+
+/// Builds a [`ServiceIdentity`] from the calling crate's `Cargo.toml` at compile time.
+#[macro_export]
+macro_rules! service_identity {
+    () => {
+        $crate::resource::ServiceIdentity::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+    };
+}
 
+static SERVICE_IDENTITY: OnceLock<ServiceIdentity> = OnceLock::new();
+
+/// Sets the service identity, falling back to the environment for any part left unset.
+/// Only the first call takes effect, so call it before `config_oltp`, e.g. with
+/// `init_service_identity(service_identity!())`.
+pub fn init_service_identity(identity: ServiceIdentity) -> &'static ServiceIdentity {
+    SERVICE_IDENTITY.get_or_init(|| identity.or_env())
+}
+
+/// The identity set with [`init_service_identity`], or the one from the environment.
+pub fn get_service_identity() -> &'static ServiceIdentity {
+    SERVICE_IDENTITY.get_or_init(ServiceIdentity::from_env)
+}
+
+static RESOURCE_DETECTORS: OnceLock<ResourceDetectors> = OnceLock::new();
+
+/// Turns on resource detectors. Like `init_service_identity`, only the first call takes effect
+/// and it must come before `config_oltp`.
+pub fn init_resource_detectors(detectors: ResourceDetectors) -> &'static ResourceDetectors {
+    RESOURCE_DETECTORS.get_or_init(|| detectors)
+}
+
+/// Shared by the tracer, logger and meter providers. Detected attributes are overridden by
+/// `OTEL_RESOURCE_ATTRIBUTES`, which in turn is overridden by the service identity.
 pub fn get_resource() -> Resource {
     static RESOURCE: OnceLock<Resource> = OnceLock::new();
     RESOURCE
         .get_or_init(|| {
-            Resource::builder()
-                .with_service_name(get_env_or_panic("CARGO_PKG_NAME"))
-                .with_attributes([
-                    KeyValue::new(SERVICE_NAME, get_env_or_panic("CARGO_PKG_NAME")),
-                    KeyValue::new(SERVICE_VERSION, get_env_or_panic("CARGO_PKG_VERSION")),
-                    KeyValue::new(
-                        DEPLOYMENT_ENVIRONMENT_NAME,
-                        get_env_or_default("CARGO_ENV", "development".to_owned()),
-                    ),
+            let identity = get_service_identity();
+            let mut attributes = vec![
+                KeyValue::new(SERVICE_NAME, identity.name.clone()),
+                KeyValue::new(
+                    DEPLOYMENT_ENVIRONMENT_NAME,
+                    get_env_or_default("CARGO_ENV", "development".to_owned()),
+                ),
+            ];
+            if let Some(version) = &identity.version {
+                attributes.push(KeyValue::new(SERVICE_VERSION, version.clone()));
+            }
+            if let Some(namespace) = &identity.namespace {
+                attributes.push(KeyValue::new(SERVICE_NAMESPACE, namespace.clone()));
+            }
+            if let Some(instance_id) = &identity.instance_id {
+                attributes.push(KeyValue::new(SERVICE_INSTANCE_ID, instance_id.clone()));
+            }
+
+            let detectors = RESOURCE_DETECTORS.get_or_init(ResourceDetectors::none);
+            Resource::builder_empty()
+                .with_detectors(&detectors.detectors())
+                .with_detectors(&[
+                    Box::new(SdkProvidedResourceDetector),
+                    Box::new(TelemetryResourceDetector),
+                    Box::new(EnvResourceDetector::new()),
                 ])
+                .with_service_name(identity.name.clone())
+                .with_attributes(attributes)
                 .build()
         })
         .clone()
 }
+
+/// `OTEL_RESOURCE_ATTRIBUTES` as `key=value` pairs, with percent-encoded values decoded.
+fn env_resource_attributes() -> Vec<(String, String)> {
+    let Ok(attributes) = std::env::var("OTEL_RESOURCE_ATTRIBUTES") else {
+        return vec![];
+    };
+    attributes
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key.trim().to_owned(), percent_decode(value.trim())))
+        .filter(|(key, _)| !key.is_empty())
+        .collect()
+}
+
+/// Decodes `%XX` escapes; anything that is not a complete escape is kept as is.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .filter(|hex| bytes[index] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_decodes_attribute_values() {
+        let cases = [
+            ("plain", "plain"),
+            ("a%20b", "a b"),
+            ("%2C%3D%25", ",=%"),
+            ("caf%C3%A9", "café"),
+            ("%e2%9c%93", "✓"),
+            ("100%", "100%"),
+            ("%2", "%2"),
+            ("%zz", "%zz"),
+            ("%+1", "%+1"),
+            ("%%41", "%A"),
+            ("%FF", "\u{FFFD}"),
+            ("é%41", "éA"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(percent_decode(value), expected, "{:?}", value);
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::resource::ResourceDetector;
+use opentelemetry_semantic_conventions::attribute::{
+    CONTAINER_ID, HOST_ARCH, HOST_NAME, K8S_NAMESPACE_NAME, K8S_NODE_NAME, K8S_POD_NAME,
+    K8S_POD_UID, OS_TYPE, PROCESS_EXECUTABLE_NAME, PROCESS_EXECUTABLE_PATH, PROCESS_PID,
+    PROCESS_RUNTIME_DESCRIPTION, PROCESS_RUNTIME_NAME, PROCESS_RUNTIME_VERSION,
+    SERVICE_INSTANCE_ID,
+};
+
+/// Set by the build script from `rustc --version`.
+const RUSTC_VERSION: &str = env!("STARLIGHT_RUSTC_VERSION");
+const CONTAINER_ID_LENGTH: usize = 64;
+
+/// Selects the detectors whose attributes `get_resource` adds; none by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceDetectors {
+    host: bool,
+    os: bool,
+    process: bool,
+    runtime: bool,
+    instance_id: bool,
+    container: bool,
+    kubernetes: bool,
+}
+
+impl ResourceDetectors {
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub fn all() -> Self {
+        ResourceDetectors {
+            host: true,
+            os: true,
+            process: true,
+            runtime: true,
+            instance_id: true,
+            container: true,
+            kubernetes: true,
+        }
+    }
+
+    pub fn with_host(mut self) -> Self {
+        self.host = true;
+        self
+    }
+
+    pub fn with_os(mut self) -> Self {
+        self.os = true;
+        self
+    }
+
+    pub fn with_process(mut self) -> Self {
+        self.process = true;
+        self
+    }
+
+    pub fn with_runtime(mut self) -> Self {
+        self.runtime = true;
+        self
+    }
+
+    /// Generates a random `service.instance.id` unless the service identity sets one.
+    pub fn with_instance_id(mut self) -> Self {
+        self.instance_id = true;
+        self
+    }
+
+    pub fn with_container(mut self) -> Self {
+        self.container = true;
+        self
+    }
+
+    pub fn with_kubernetes(mut self) -> Self {
+        self.kubernetes = true;
+        self
+    }
+
+    pub(crate) fn detectors(&self) -> Vec<Box<dyn ResourceDetector>> {
+        let mut detectors: Vec<Box<dyn ResourceDetector>> = vec![];
+        if self.host {
+            detectors.push(Box::new(HostResourceDetector));
+        }
+        if self.os {
+            detectors.push(Box::new(OsResourceDetector));
+        }
+        if self.process {
+            detectors.push(Box::new(ProcessResourceDetector));
+        }
+        if self.runtime {
+            detectors.push(Box::new(RuntimeResourceDetector));
+        }
+        if self.instance_id {
+            detectors.push(Box::new(ServiceInstanceIdDetector));
+        }
+        if self.container {
+            detectors.push(Box::new(ContainerResourceDetector));
+        }
+        if self.kubernetes {
+            detectors.push(Box::new(KubernetesResourceDetector));
+        }
+        detectors
+    }
+}
+
+/// `host.name` and `host.arch`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostResourceDetector;
+
+impl ResourceDetector for HostResourceDetector {
+    fn detect(&self) -> Resource {
+        let mut attributes = vec![KeyValue::new(HOST_ARCH, host_arch())];
+        if let Some(host_name) = host_name() {
+            attributes.push(KeyValue::new(HOST_NAME, host_name));
+        }
+        Resource::builder_empty()
+            .with_attributes(attributes)
+            .build()
+    }
+}
+
+fn host_name() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+}
+
+/// Maps Rust's architecture names onto the semantic convention values.
+fn host_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "arm" => "arm32",
+        "powerpc" => "ppc32",
+        "powerpc64" => "ppc64",
+        other => other,
+    }
+}
+
+/// `os.type`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsResourceDetector;
+
+impl ResourceDetector for OsResourceDetector {
+    fn detect(&self) -> Resource {
+        let os_type = match std::env::consts::OS {
+            "macos" => "darwin",
+            other => other,
+        };
+        Resource::builder_empty()
+            .with_attribute(KeyValue::new(OS_TYPE, os_type))
+            .build()
+    }
+}
+
+/// `process.pid` and the executable's name and path.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessResourceDetector;
+
+impl ResourceDetector for ProcessResourceDetector {
+    fn detect(&self) -> Resource {
+        let mut attributes = vec![KeyValue::new(PROCESS_PID, i64::from(std::process::id()))];
+        if let Ok(executable) = std::env::current_exe() {
+            if let Some(name) = executable.file_name() {
+                attributes.push(KeyValue::new(
+                    PROCESS_EXECUTABLE_NAME,
+                    name.to_string_lossy().into_owned(),
+                ));
+            }
+            attributes.push(KeyValue::new(
+                PROCESS_EXECUTABLE_PATH,
+                executable.to_string_lossy().into_owned(),
+            ));
+        }
+        Resource::builder_empty()
+            .with_attributes(attributes)
+            .build()
+    }
+}
+
+/// `process.runtime.name`, `.version` and `.description` of the compiler that built the crate.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RuntimeResourceDetector;
+
+impl ResourceDetector for RuntimeResourceDetector {
+    fn detect(&self) -> Resource {
+        let version = RUSTC_VERSION.split_whitespace().nth(1).unwrap_or("unknown");
+        Resource::builder_empty()
+            .with_attributes([
+                KeyValue::new(PROCESS_RUNTIME_NAME, "rustc"),
+                KeyValue::new(PROCESS_RUNTIME_VERSION, version),
+                KeyValue::new(PROCESS_RUNTIME_DESCRIPTION, RUSTC_VERSION),
+            ])
+            .build()
+    }
+}
+
+/// A random UUID as `service.instance.id`, different on every start.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ServiceInstanceIdDetector;
+
+impl ResourceDetector for ServiceInstanceIdDetector {
+    fn detect(&self) -> Resource {
+        Resource::builder_empty()
+            .with_attribute(KeyValue::new(
+                SERVICE_INSTANCE_ID,
+                uuid::Uuid::new_v4().to_string(),
+            ))
+            .build()
+    }
+}
+
+/// `container.id` from the cgroup paths (cgroup v1) or, with cgroup v2, from the source of the
+/// `/etc/hostname` mount that docker, containerd and CRI-O set up per container.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ContainerResourceDetector;
+
+impl ResourceDetector for ContainerResourceDetector {
+    fn detect(&self) -> Resource {
+        let from_cgroup =
+            || cgroup_container_id(&std::fs::read_to_string("/proc/self/cgroup").ok()?);
+        let from_mounts =
+            || mountinfo_container_id(&std::fs::read_to_string("/proc/self/mountinfo").ok()?);
+        match from_cgroup().or_else(from_mounts) {
+            Some(container_id) => Resource::builder_empty()
+                .with_attribute(KeyValue::new(CONTAINER_ID, container_id))
+                .build(),
+            None => Resource::builder_empty().build(),
+        }
+    }
+}
+
+fn cgroup_container_id(cgroup: &str) -> Option<String> {
+    cgroup.lines().find_map(container_id)
+}
+
+/// Other mounts carry image layer digests, which look just like container ids.
+fn mountinfo_container_id(mountinfo: &str) -> Option<String> {
+    mountinfo
+        .lines()
+        .filter(|line| line.split_whitespace().nth(4) == Some("/etc/hostname"))
+        .find_map(container_id)
+}
+
+/// The first 64 hex digit segment of a line such as
+/// `0::/kubepods/burstable/pod1234/cri-containerd-<id>.scope`.
+fn container_id(line: &str) -> Option<String> {
+    line.split(|c: char| !c.is_ascii_alphanumeric())
+        .find(|segment| {
+            segment.len() == CONTAINER_ID_LENGTH && segment.chars().all(|c| c.is_ascii_hexdigit())
+        })
+        .map(str::to_owned)
+}
+
+/// `k8s.pod.name`, `k8s.pod.uid`, `k8s.namespace.name` and `k8s.node.name` from environment
+/// variables set through the downward API, e.g.
+///
+/// ```yaml
+/// env:
+///   - name: K8S_POD_NAME
+///     valueFrom:
+///       fieldRef:
+///         fieldPath: metadata.name
+/// ```
+///
+/// `POD_NAME`, `POD_UID`, `POD_NAMESPACE` and `NODE_NAME` are accepted as well.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KubernetesResourceDetector;
+
+impl ResourceDetector for KubernetesResourceDetector {
+    fn detect(&self) -> Resource {
+        let variables = [
+            (K8S_POD_NAME, ["K8S_POD_NAME", "POD_NAME"]),
+            (K8S_POD_UID, ["K8S_POD_UID", "POD_UID"]),
+            (K8S_NAMESPACE_NAME, ["K8S_NAMESPACE_NAME", "POD_NAMESPACE"]),
+            (K8S_NODE_NAME, ["K8S_NODE_NAME", "NODE_NAME"]),
+        ];
+        let attributes = variables.into_iter().filter_map(|(key, names)| {
+            names
+                .iter()
+                .filter_map(|name| std::env::var(name).ok())
+                .find(|value| !value.is_empty())
+                .map(|value| KeyValue::new(key, value))
+        });
+        Resource::builder_empty()
+            .with_attributes(attributes)
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "3f4e5d6c7b8a99887766554433221100ffeeddccbbaa00112233445566778899";
+
+    #[test]
+    fn finds_the_container_id_in_cgroup_lines() {
+        let cases = [
+            (format!("12:pids:/docker/{ID}"), Some(ID)),
+            (format!("0::/system.slice/docker-{ID}.scope"), Some(ID)),
+            (
+                format!("0::/kubepods/burstable/pod1234/cri-containerd-{ID}.scope"),
+                Some(ID),
+            ),
+            (
+                format!("1:name=systemd:/kubepods/besteffort/pod5678/crio-{ID}"),
+                Some(ID),
+            ),
+            (format!("0::/ecs/task/{ID}"), Some(ID)),
+            ("0::/".to_owned(), None),
+            (
+                "0::/user.slice/user-1000.slice/session-2.scope".to_owned(),
+                None,
+            ),
+            (format!("0::/docker/{}", &ID[1..]), None),
+            (format!("0::/docker/{ID}0"), None),
+            (format!("0::/docker/{}", ID.replace('f', "g")), None),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(cgroup_container_id(&line).as_deref(), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn finds_the_container_id_in_the_hostname_mount() {
+        let layer = "ab".repeat(32);
+        let mountinfo = format!(
+            "611 610 0:52 / / rw,relatime - overlay overlay lowerdir=/overlay2/{layer}\n\
+             640 611 0:57 / /proc rw,nosuid - proc proc rw\n\
+             652 611 254:1 /docker/containers/{ID}/hostname /etc/hostname rw - ext4 /dev/vda1 rw\n"
+        );
+        assert_eq!(mountinfo_container_id(&mountinfo).as_deref(), Some(ID));
+
+        let cases = [
+            format!(
+                "611 610 0:52 / / rw - overlay overlay lowerdir=/overlay2/{layer}"
+            ),
+            format!(
+                "652 611 254:1 /docker/containers/{ID}/hosts /etc/hosts rw - ext4 /dev/vda1 rw"
+            ),
+            "652 611 254:1 /etc/hostname /etc/hostname rw - ext4 /dev/vda1 rw".to_owned(),
+            "29 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw".to_owned(),
+        ];
+        for line in cases {
+            assert_eq!(mountinfo_container_id(&line), None, "{}", line);
+        }
+    }
+}
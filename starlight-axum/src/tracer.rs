@@ -10,6 +10,11 @@ pub fn get_tracer_provider() -> &'static SdkTracerProvider {
         .expect("Failed to get tracer provider")
 }
 
+/// The provider, if one was installed.
+pub(crate) fn try_get_tracer_provider() -> Option<&'static SdkTracerProvider> {
+    SDK_TRACER_PROVIDER.get()
+}
+
 pub fn get_or_init_tracer_provider(oltp_grpc_url: &str) -> SdkTracerProvider {
     SDK_TRACER_PROVIDER
         .get_or_init(|| {
@@ -28,3 +33,15 @@ pub fn get_or_init_tracer_provider(oltp_grpc_url: &str) -> SdkTracerProvider {
         })
         .clone()
 }
+
+/// Provider without an exporter, for when traces are turned off: spans are still created and
+/// propagated, but never exported.
+pub fn get_or_init_noop_tracer_provider() -> SdkTracerProvider {
+    SDK_TRACER_PROVIDER
+        .get_or_init(|| {
+            SdkTracerProvider::builder()
+                .with_resource(get_resource())
+                .build()
+        })
+        .clone()
+}
@@ -0,0 +1,252 @@
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+/// Ordered from best to worst, so the aggregate of several checks is their maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HealthStatus {
+    Healthy,
+    /// Working, but with reduced capacity or quality; still counts as ready.
+    Degraded,
+    Unhealthy,
+}
+
+impl HealthStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HealthStatus::Healthy => "healthy",
+            HealthStatus::Degraded => "degraded",
+            HealthStatus::Unhealthy => "unhealthy",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Health {
+    pub status: HealthStatus,
+    pub details: Option<String>,
+}
+
+impl Health {
+    pub fn healthy() -> Self {
+        Health {
+            status: HealthStatus::Healthy,
+            details: None,
+        }
+    }
+
+    pub fn degraded(details: impl Into<String>) -> Self {
+        Health {
+            status: HealthStatus::Degraded,
+            details: Some(details.into()),
+        }
+    }
+
+    pub fn unhealthy(details: impl Into<String>) -> Self {
+        Health {
+            status: HealthStatus::Unhealthy,
+            details: Some(details.into()),
+        }
+    }
+}
+
+/// A probe run every time health is queried, e.g. a database ping.
+#[async_trait]
+pub trait HealthCheck: Send + Sync + 'static {
+    async fn check(&self) -> Health;
+}
+
+#[async_trait]
+impl<F, Fut> HealthCheck for F
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Health> + Send,
+{
+    async fn check(&self) -> Health {
+        self().await
+    }
+}
+
+/// Result of running every registered check once.
+#[derive(Debug, Clone)]
+pub struct HealthSnapshot {
+    pub status: HealthStatus,
+    pub shutting_down: bool,
+    pub checks: BTreeMap<String, Health>,
+}
+
+impl HealthSnapshot {
+    /// Ready to take traffic: not shutting down and nothing unhealthy.
+    pub fn is_ready(&self) -> bool {
+        !self.shutting_down && self.status != HealthStatus::Unhealthy
+    }
+}
+
+struct Registry {
+    checks: Mutex<BTreeMap<String, Arc<dyn HealthCheck>>>,
+    shutting_down: AtomicBool,
+    check_timeout: Duration,
+}
+
+/// Named health checks shared between services and the probe endpoints.
+#[derive(Clone)]
+pub struct HealthRegistry {
+    inner: Arc<Registry>,
+}
+
+impl Default for HealthRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HealthRegistry {
+    pub fn new() -> Self {
+        Self::with_check_timeout(Duration::from_secs(5))
+    }
+
+    /// A check that has not answered within `check_timeout` is reported unhealthy.
+    pub fn with_check_timeout(check_timeout: Duration) -> Self {
+        HealthRegistry {
+            inner: Arc::new(Registry {
+                checks: Mutex::new(BTreeMap::new()),
+                shutting_down: AtomicBool::new(false),
+                check_timeout,
+            }),
+        }
+    }
+
+    /// Adds a check, replacing any check already registered under `name`.
+    pub fn register(&self, name: impl Into<String>, check: impl HealthCheck) {
+        self.checks().insert(name.into(), Arc::new(check));
+    }
+
+    pub fn deregister(&self, name: &str) {
+        self.checks().remove(name);
+    }
+
+    /// Makes readiness fail from now on, while the checks keep reporting as before.
+    pub fn mark_shutting_down(&self) {
+        self.inner.shutting_down.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.inner.shutting_down.load(Ordering::Relaxed)
+    }
+
+    /// Runs all checks concurrently.
+    pub async fn check(&self) -> HealthSnapshot {
+        let checks = self
+            .checks()
+            .iter()
+            .map(|(name, check)| (name.clone(), check.clone()))
+            .collect::<Vec<_>>();
+
+        let timeout = self.inner.check_timeout;
+        let mut running = JoinSet::new();
+        let mut names = HashMap::new();
+        for (name, check) in checks {
+            let abort_handle = running.spawn(async move {
+                tokio::time::timeout(timeout, check.check())
+                    .await
+                    .unwrap_or_else(|_| Health::unhealthy(format!("no answer within {:?}", timeout)))
+            });
+            names.insert(abort_handle.id(), name);
+        }
+
+        let mut results = BTreeMap::new();
+        while let Some(joined) = running.join_next_with_id().await {
+            let (id, health) = match joined {
+                Ok((id, health)) => (id, health),
+                Err(err) => (err.id(), Health::unhealthy("check panicked")),
+            };
+            if let Some(name) = names.remove(&id) {
+                results.insert(name, health);
+            }
+        }
+
+        HealthSnapshot {
+            status: results
+                .values()
+                .map(|health| health.status)
+                .max()
+                .unwrap_or(HealthStatus::Healthy),
+            shutting_down: self.is_shutting_down(),
+            checks: results,
+        }
+    }
+
+    fn checks(&self) -> MutexGuard<'_, BTreeMap<String, Arc<dyn HealthCheck>>> {
+        self.inner
+            .checks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn aggregates_to_the_worst_status() {
+        let health = HealthRegistry::new();
+        let snapshot = health.check().await;
+        assert_eq!(snapshot.status, HealthStatus::Healthy);
+        assert!(snapshot.is_ready());
+
+        health.register("db", || async { Health::healthy() });
+        health.register("cache", || async { Health::degraded("slow") });
+        let snapshot = health.check().await;
+        assert_eq!(snapshot.status, HealthStatus::Degraded);
+        assert!(snapshot.is_ready());
+        assert_eq!(snapshot.checks["cache"], Health::degraded("slow"));
+
+        health.register("queue", || async { Health::unhealthy("down") });
+        let snapshot = health.check().await;
+        assert_eq!(snapshot.status, HealthStatus::Unhealthy);
+        assert!(!snapshot.is_ready());
+
+        health.deregister("queue");
+        assert_eq!(health.check().await.status, HealthStatus::Degraded);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reports_slow_and_panicking_checks_as_unhealthy() {
+        let health = HealthRegistry::with_check_timeout(Duration::from_secs(1));
+        health.register("slow", || async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Health::healthy()
+        });
+        health.register("broken", || async { panic!("check failed") });
+        health.register("db", || async { Health::healthy() });
+
+        let snapshot = health.check().await;
+        assert_eq!(
+            snapshot.checks["slow"],
+            Health::unhealthy("no answer within 1s")
+        );
+        assert_eq!(
+            snapshot.checks["broken"],
+            Health::unhealthy("check panicked")
+        );
+        assert_eq!(snapshot.checks["db"], Health::healthy());
+        assert_eq!(snapshot.status, HealthStatus::Unhealthy);
+    }
+
+    #[tokio::test]
+    async fn shutting_down_fails_readiness_but_not_the_checks() {
+        let health = HealthRegistry::new();
+        health.register("db", || async { Health::healthy() });
+        health.mark_shutting_down();
+
+        let snapshot = health.check().await;
+        assert!(snapshot.shutting_down);
+        assert_eq!(snapshot.status, HealthStatus::Healthy);
+        assert!(!snapshot.is_ready());
+    }
+}
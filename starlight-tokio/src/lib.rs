@@ -1,3 +1,26 @@
+mod health;
+mod restart;
 mod runnable_service;
+mod runtime_metrics;
+mod scheduler;
+mod shutdown;
+mod signal;
+mod supervisor;
+mod worker_pool;
 
-pub use runnable_service::StarlightService;
\ No newline at end of file
+#[macro_use]
+extern crate tracing;
+
+pub use health::{Health, HealthCheck, HealthRegistry, HealthSnapshot, HealthStatus};
+pub use restart::{RestartMode, RestartPolicy};
+pub use runnable_service::{ServiceError, ServiceMetadata, ServiceResult, StarlightService};
+pub use runtime_metrics::RuntimeMetricsCollector;
+pub use scheduler::{Job, Schedule, Scheduler};
+pub use shutdown::{ShutdownReason, ShutdownToken};
+pub use signal::{Signal, SignalListener};
+pub use supervisor::{
+    ServiceExit, ServiceOptions, ServiceReport, Supervisor, SupervisorError, SupervisorReport,
+};
+pub use worker_pool::{
+    Backpressure, SubmitError, WorkerPool, WorkerPoolConfig, WorkerPoolHandle,
+};
@@ -0,0 +1,236 @@
+use rand::Rng;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartMode {
+    Never,
+    Always,
+    /// Restart only after an error, panic or cancellation, not after a clean return.
+    OnFailure,
+}
+
+/// Decides whether and when the supervisor restarts a service that exited on its own.
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    mode: RestartMode,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    jitter: f64,
+    max_restarts: usize,
+    window: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self::never()
+    }
+}
+
+impl RestartPolicy {
+    pub fn never() -> Self {
+        RestartPolicy {
+            mode: RestartMode::Never,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.1,
+            max_restarts: 5,
+            window: Duration::from_secs(60),
+        }
+    }
+
+    pub fn always() -> Self {
+        RestartPolicy {
+            mode: RestartMode::Always,
+            ..Self::never()
+        }
+    }
+
+    pub fn on_failure() -> Self {
+        RestartPolicy {
+            mode: RestartMode::OnFailure,
+            ..Self::never()
+        }
+    }
+
+    /// Delay before the first restart and the cap the exponential backoff grows to.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Randomizes each delay by up to `jitter` of its value in either direction, e.g. `0.1` for ±10%.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Allows at most `max_restarts` within any `window`; exceeding it shuts the supervisor down.
+    pub fn with_max_restarts(mut self, max_restarts: usize, window: Duration) -> Self {
+        self.max_restarts = max_restarts;
+        self.window = window;
+        self
+    }
+
+    pub fn mode(&self) -> RestartMode {
+        self.mode
+    }
+
+    pub(crate) fn applies_to(&self, failed: bool) -> bool {
+        match self.mode {
+            RestartMode::Never => false,
+            RestartMode::Always => true,
+            RestartMode::OnFailure => failed,
+        }
+    }
+
+    fn backoff(&self, attempt: usize) -> Duration {
+        let exponent = i32::try_from(attempt).unwrap_or(i32::MAX);
+        let delay = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let delay = delay.min(self.max_backoff.as_secs_f64());
+        let jitter = if self.jitter > 0.0 {
+            rand::rng().random_range(-self.jitter..=self.jitter)
+        } else {
+            0.0
+        };
+        Duration::from_secs_f64((delay * (1.0 + jitter)).max(0.0))
+    }
+}
+
+pub(crate) enum RestartDecision {
+    After(Duration),
+    BudgetExhausted,
+}
+
+/// Restart history of one service, used to enforce the restart budget.
+#[derive(Debug, Default)]
+pub(crate) struct RestartTracker {
+    restarts: VecDeque<Instant>,
+    total: u32,
+}
+
+impl RestartTracker {
+    pub(crate) fn next(&mut self, policy: &RestartPolicy) -> RestartDecision {
+        let now = Instant::now();
+        while let Some(oldest) = self.restarts.front() {
+            if now.duration_since(*oldest) > policy.window {
+                self.restarts.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if self.restarts.len() >= policy.max_restarts {
+            return RestartDecision::BudgetExhausted;
+        }
+
+        let delay = policy.backoff(self.restarts.len());
+        self.restarts.push_back(now);
+        self.total += 1;
+        RestartDecision::After(delay)
+    }
+
+    pub(crate) fn total(&self) -> u32 {
+        self.total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delay(decision: RestartDecision) -> Duration {
+        match decision {
+            RestartDecision::After(delay) => delay,
+            RestartDecision::BudgetExhausted => panic!("restart budget exhausted"),
+        }
+    }
+
+    fn policy() -> RestartPolicy {
+        RestartPolicy::always()
+            .with_backoff(Duration::from_millis(100), Duration::from_secs(1))
+            .with_jitter(0.0)
+    }
+
+    #[test]
+    fn backoff_grows_by_the_multiplier_up_to_the_cap() {
+        let policy = policy().with_multiplier(3.0);
+        let delays: Vec<_> = (0..5).map(|attempt| policy.backoff(attempt)).collect();
+        let expected = [100, 300, 900, 1000, 1000].map(Duration::from_millis);
+        for (delay, expected) in delays.iter().zip(expected) {
+            assert!(
+                delay.abs_diff(expected) < Duration::from_micros(1),
+                "{:?}",
+                delays
+            );
+        }
+    }
+
+    #[test]
+    fn multiplier_and_jitter_are_clamped() {
+        let policy = policy().with_multiplier(0.5).with_jitter(2.0);
+        assert_eq!(policy.multiplier, 1.0);
+        assert_eq!(policy.jitter, 1.0);
+        assert!(policy.backoff(10) <= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let policy = policy().with_jitter(0.25);
+        for _ in 0..1000 {
+            let delay = policy.backoff(1);
+            assert!(delay >= Duration::from_millis(150) && delay <= Duration::from_millis(250));
+        }
+    }
+
+    #[test]
+    fn modes_decide_which_exits_are_restarted() {
+        assert!(!RestartPolicy::never().applies_to(true));
+        assert!(RestartPolicy::always().applies_to(false));
+        assert!(RestartPolicy::on_failure().applies_to(true));
+        assert!(!RestartPolicy::on_failure().applies_to(false));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn allows_max_restarts_within_the_window() {
+        let policy = policy().with_max_restarts(3, Duration::from_secs(10));
+        let mut tracker = RestartTracker::default();
+        assert_eq!(delay(tracker.next(&policy)), Duration::from_millis(100));
+        tokio::time::advance(Duration::from_secs(4)).await;
+        assert_eq!(delay(tracker.next(&policy)), Duration::from_millis(200));
+        tokio::time::advance(Duration::from_secs(4)).await;
+        assert_eq!(delay(tracker.next(&policy)), Duration::from_millis(400));
+        assert!(matches!(
+            tracker.next(&policy),
+            RestartDecision::BudgetExhausted
+        ));
+
+        // The first restart leaves the window, freeing one slot.
+        tokio::time::advance(Duration::from_secs(3)).await;
+        assert_eq!(delay(tracker.next(&policy)), Duration::from_millis(400));
+        assert!(matches!(
+            tracker.next(&policy),
+            RestartDecision::BudgetExhausted
+        ));
+        assert_eq!(tracker.total(), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff_resets_once_restarts_leave_the_window() {
+        let policy = policy().with_max_restarts(5, Duration::from_secs(10));
+        let mut tracker = RestartTracker::default();
+        delay(tracker.next(&policy));
+        delay(tracker.next(&policy));
+        tokio::time::advance(Duration::from_secs(11)).await;
+        assert_eq!(delay(tracker.next(&policy)), Duration::from_millis(100));
+    }
+}
@@ -1,9 +1,52 @@
-use std::sync::Arc;
+use crate::health::Health;
+use crate::shutdown::ShutdownToken;
+use std::collections::BTreeMap;
+use std::error::Error;
 use async_trait::async_trait;
-use tokio::sync::watch;
-use tokio::task::JoinHandle;
 
+pub type ServiceError = Box<dyn Error + Send + Sync + 'static>;
+pub type ServiceResult = Result<(), ServiceError>;
+
+/// Descriptive information a service publishes about itself, e.g. for health endpoints.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceMetadata {
+    pub version: Option<String>,
+    pub description: Option<String>,
+    pub labels: BTreeMap<String, String>,
+}
+
+/// A long-running unit of work with an explicit lifecycle:
+/// `start` → `run` (with `ready` awaited alongside it) → `stop`.
 #[async_trait]
-pub trait StarlightService {
-    fn run(&self, shutdown_tx: Arc<watch::Sender<bool>>, shutdown_rx: watch::Receiver<bool>, ) -> JoinHandle<()>;
-}
\ No newline at end of file
+pub trait StarlightService: Send + Sync + 'static {
+    /// Unique name used in logs, reports and for dependencies between services.
+    fn name(&self) -> &str;
+
+    fn metadata(&self) -> ServiceMetadata {
+        ServiceMetadata::default()
+    }
+
+    /// One-time initialization before `run`, e.g. opening connections. An error fails the service.
+    async fn start(&self) -> ServiceResult {
+        Ok(())
+    }
+
+    /// Resolves once the service can take work. Polled concurrently with `run`.
+    async fn ready(&self) -> ServiceResult {
+        Ok(())
+    }
+
+    /// Does the work until `shutdown` drains, returning the reason if it fails. In-flight work
+    /// may be finished while draining, but should be abandoned once `shutdown` is cancelled.
+    async fn run(&self, shutdown: ShutdownToken) -> ServiceResult;
+
+    /// Reported by health endpoints while the service is ready, e.g. to flag degraded operation.
+    async fn health(&self) -> Health {
+        Health::healthy()
+    }
+
+    /// Releases resources after `run` returned, whether it succeeded or not.
+    async fn stop(&self) -> ServiceResult {
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+use crate::StarlightService;
+use crate::runnable_service::ServiceResult;
+use crate::shutdown::ShutdownToken;
+use async_trait::async_trait;
+use opentelemetry::KeyValue;
+use opentelemetry::metrics::{AsyncInstrument, Meter};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+const WORKER_INDEX: &str = "tokio.worker.index";
+
+#[derive(Debug, Default)]
+struct RuntimeSnapshot {
+    workers: u64,
+    alive_tasks: u64,
+    global_queue_depth: u64,
+    worker_busy_ratio: Vec<f64>,
+    worker_park_count: Vec<u64>,
+    #[cfg(tokio_unstable)]
+    blocking_threads: u64,
+    #[cfg(tokio_unstable)]
+    idle_blocking_threads: u64,
+    #[cfg(tokio_unstable)]
+    blocking_queue_depth: u64,
+    #[cfg(tokio_unstable)]
+    worker_poll_count: Vec<u64>,
+}
+
+/// Samples `tokio::runtime::RuntimeMetrics` on an interval and exposes the latest sample as
+/// observable instruments. Blocking-pool and poll-count metrics need `--cfg tokio_unstable`.
+pub struct RuntimeMetricsCollector {
+    interval: Duration,
+    snapshot: Arc<Mutex<RuntimeSnapshot>>,
+}
+
+impl RuntimeMetricsCollector {
+    pub fn new(meter: &Meter) -> Self {
+        let snapshot = Arc::new(Mutex::new(RuntimeSnapshot::default()));
+        register_instruments(meter, &snapshot);
+        RuntimeMetricsCollector {
+            interval: Duration::from_secs(5),
+            snapshot,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        if !interval.is_zero() {
+            self.interval = interval;
+        }
+        self
+    }
+}
+
+#[async_trait]
+impl StarlightService for RuntimeMetricsCollector {
+    fn name(&self) -> &str {
+        "tokio-runtime-metrics"
+    }
+
+    async fn run(&self, shutdown: ShutdownToken) -> ServiceResult {
+        let mut sampler = Sampler::new(Handle::current());
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let sample = sampler.sample();
+                    *self.snapshot.lock().unwrap_or_else(PoisonError::into_inner) = sample;
+                }
+                _ = shutdown.draining() => return Ok(()),
+            }
+        }
+    }
+}
+
+struct Sampler {
+    handle: Handle,
+    last_sampled_at: Instant,
+    last_busy: Vec<Duration>,
+}
+
+impl Sampler {
+    /// Seeded with the current busy durations, so the first ratio covers only the first interval
+    /// and not everything since the runtime started.
+    fn new(handle: Handle) -> Self {
+        let last_busy = busy_durations(&handle);
+        Sampler {
+            handle,
+            last_sampled_at: Instant::now(),
+            last_busy,
+        }
+    }
+
+    fn sample(&mut self) -> RuntimeSnapshot {
+        let metrics = self.handle.metrics();
+        let workers = metrics.num_workers();
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_sampled_at);
+        self.last_sampled_at = now;
+
+        let mut snapshot = RuntimeSnapshot {
+            workers: workers as u64,
+            alive_tasks: metrics.num_alive_tasks() as u64,
+            global_queue_depth: metrics.global_queue_depth() as u64,
+            ..RuntimeSnapshot::default()
+        };
+
+        #[cfg(target_has_atomic = "64")]
+        {
+            let busy = busy_durations(&self.handle);
+            snapshot.worker_busy_ratio = busy_ratios(&self.last_busy, &busy, elapsed);
+            snapshot.worker_park_count = (0..workers)
+                .map(|worker| metrics.worker_park_count(worker))
+                .collect();
+            self.last_busy = busy;
+        }
+
+        #[cfg(tokio_unstable)]
+        {
+            snapshot.blocking_threads = metrics.num_blocking_threads() as u64;
+            snapshot.idle_blocking_threads = metrics.num_idle_blocking_threads() as u64;
+            snapshot.blocking_queue_depth = metrics.blocking_queue_depth() as u64;
+            #[cfg(target_has_atomic = "64")]
+            {
+                snapshot.worker_poll_count = (0..workers)
+                    .map(|worker| metrics.worker_poll_count(worker))
+                    .collect();
+            }
+        }
+
+        snapshot
+    }
+}
+
+/// Total busy duration of each worker since the runtime started.
+fn busy_durations(handle: &Handle) -> Vec<Duration> {
+    #[cfg(target_has_atomic = "64")]
+    {
+        let metrics = handle.metrics();
+        (0..metrics.num_workers())
+            .map(|worker| metrics.worker_total_busy_duration(worker))
+            .collect()
+    }
+    #[cfg(not(target_has_atomic = "64"))]
+    {
+        let _ = handle;
+        vec![]
+    }
+}
+
+/// Share of `elapsed` each worker spent busy, from its total busy duration at the previous and
+/// the current sample.
+fn busy_ratios(previous: &[Duration], busy: &[Duration], elapsed: Duration) -> Vec<f64> {
+    let elapsed = elapsed.as_secs_f64();
+    busy.iter()
+        .enumerate()
+        .map(|(worker, total)| {
+            let previous = previous.get(worker).copied().unwrap_or_default();
+            if elapsed > 0.0 {
+                (total.saturating_sub(previous).as_secs_f64() / elapsed).min(1.0)
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+fn register_instruments(meter: &Meter, snapshot: &Arc<Mutex<RuntimeSnapshot>>) {
+    let read = |snapshot: &Arc<Mutex<RuntimeSnapshot>>, field: fn(&RuntimeSnapshot) -> u64| {
+        let snapshot = snapshot.clone();
+        move |observer: &dyn AsyncInstrument<u64>| {
+            let value = field(&snapshot.lock().unwrap_or_else(PoisonError::into_inner));
+            observer.observe(value, &[]);
+        }
+    };
+
+    meter
+        .u64_observable_gauge("tokio.runtime.workers")
+        .with_description("Number of worker threads used by the runtime")
+        .with_unit("{thread}")
+        .with_callback(read(snapshot, |s| s.workers))
+        .build();
+    meter
+        .u64_observable_gauge("tokio.runtime.alive_tasks")
+        .with_description("Number of tasks currently alive in the runtime")
+        .with_unit("{task}")
+        .with_callback(read(snapshot, |s| s.alive_tasks))
+        .build();
+    meter
+        .u64_observable_gauge("tokio.runtime.global_queue.depth")
+        .with_description("Number of tasks waiting in the runtime's global queue")
+        .with_unit("{task}")
+        .with_callback(read(snapshot, |s| s.global_queue_depth))
+        .build();
+
+    let busy_snapshot = snapshot.clone();
+    meter
+        .f64_observable_gauge("tokio.worker.busy.ratio")
+        .with_description("Share of the last sampling interval each worker spent busy")
+        .with_unit("1")
+        .with_callback(move |observer| {
+            let snapshot = busy_snapshot.lock().unwrap_or_else(PoisonError::into_inner);
+            for (worker, ratio) in snapshot.worker_busy_ratio.iter().enumerate() {
+                observer.observe(*ratio, &[KeyValue::new(WORKER_INDEX, worker as i64)]);
+            }
+        })
+        .build();
+
+    let park_snapshot = snapshot.clone();
+    meter
+        .u64_observable_counter("tokio.worker.park.count")
+        .with_description("Total number of times each worker has parked")
+        .with_unit("{park}")
+        .with_callback(move |observer| {
+            let snapshot = park_snapshot.lock().unwrap_or_else(PoisonError::into_inner);
+            for (worker, count) in snapshot.worker_park_count.iter().enumerate() {
+                observer.observe(*count, &[KeyValue::new(WORKER_INDEX, worker as i64)]);
+            }
+        })
+        .build();
+
+    #[cfg(tokio_unstable)]
+    {
+        meter
+            .u64_observable_gauge("tokio.runtime.blocking_threads")
+            .with_description("Number of threads in the blocking pool")
+            .with_unit("{thread}")
+            .with_callback(read(snapshot, |s| s.blocking_threads))
+            .build();
+        meter
+            .u64_observable_gauge("tokio.runtime.idle_blocking_threads")
+            .with_description("Number of idle threads in the blocking pool")
+            .with_unit("{thread}")
+            .with_callback(read(snapshot, |s| s.idle_blocking_threads))
+            .build();
+        meter
+            .u64_observable_gauge("tokio.runtime.blocking_queue.depth")
+            .with_description("Number of tasks waiting for a blocking thread")
+            .with_unit("{task}")
+            .with_callback(read(snapshot, |s| s.blocking_queue_depth))
+            .build();
+
+        let poll_snapshot = snapshot.clone();
+        meter
+            .u64_observable_counter("tokio.worker.poll.count")
+            .with_description("Total number of tasks polled by each worker")
+            .with_unit("{poll}")
+            .with_callback(move |observer| {
+                let snapshot = poll_snapshot.lock().unwrap_or_else(PoisonError::into_inner);
+                for (worker, count) in snapshot.worker_poll_count.iter().enumerate() {
+                    observer.observe(*count, &[KeyValue::new(WORKER_INDEX, worker as i64)]);
+                }
+            })
+            .build();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(values: &[u64]) -> Vec<Duration> {
+        values.iter().copied().map(Duration::from_millis).collect()
+    }
+
+    #[test]
+    fn busy_ratio_is_the_busy_share_of_the_interval() {
+        let ratios = busy_ratios(
+            &millis(&[1_000, 2_000]),
+            &millis(&[1_250, 3_000]),
+            Duration::from_secs(1),
+        );
+        assert_eq!(ratios, [0.25, 1.0]);
+    }
+
+    #[test]
+    fn busy_ratio_is_capped_and_zero_without_elapsed_time() {
+        let ratios = busy_ratios(&millis(&[0]), &millis(&[1_500]), Duration::from_secs(1));
+        assert_eq!(ratios, [1.0]);
+        let ratios = busy_ratios(&millis(&[0]), &millis(&[1_500]), Duration::ZERO);
+        assert_eq!(ratios, [0.0]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn first_sample_only_covers_the_first_interval() {
+        // Keep the workers busy before the sampler exists.
+        let started = Instant::now();
+        let busy = (0..2)
+            .map(|_| {
+                tokio::spawn(async move {
+                    while started.elapsed() < Duration::from_millis(200) {
+                        std::hint::spin_loop();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in busy {
+            task.await.unwrap();
+        }
+        // Workers publish their busy time when they park.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let mut sampler = Sampler::new(Handle::current());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let snapshot = sampler.sample();
+        assert_eq!(snapshot.workers, 2);
+        for ratio in snapshot.worker_busy_ratio {
+            assert!(ratio < 0.5, "idle worker reported busy ratio {}", ratio);
+        }
+    }
+}
@@ -0,0 +1,417 @@
+use crate::StarlightService;
+use crate::runnable_service::{ServiceError, ServiceResult};
+use crate::shutdown::ShutdownToken;
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+use opentelemetry::KeyValue;
+use opentelemetry::metrics::{Counter, Histogram, Meter};
+use rand::Rng;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::Instrument;
+
+const JOB_NAME: &str = "job.name";
+const JOB_OUTCOME: &str = "job.outcome";
+
+type JobFuture = Pin<Box<dyn Future<Output = ServiceResult> + Send>>;
+type JobFn = Arc<dyn Fn() -> JobFuture + Send + Sync>;
+
+/// When a job runs; built with [`Schedule::every`] or [`Schedule::cron`].
+#[derive(Clone)]
+pub struct Schedule(Kind);
+
+/// Kept private so an interval can only be built through the non-zero check in `every`.
+#[derive(Clone)]
+enum Kind {
+    Interval(Duration),
+    /// Evaluated in UTC.
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    pub fn every(period: Duration) -> Self {
+        assert!(!period.is_zero(), "schedule period must be non-zero");
+        Schedule(Kind::Interval(period))
+    }
+
+    /// Parses a cron expression; the seconds field is optional, so both `*/5 * * * *` and
+    /// `0 */5 * * * *` run every five minutes.
+    pub fn cron(expression: &str) -> Result<Self, cron::error::Error> {
+        let expression = if expression.split_whitespace().count() == 5 {
+            format!("0 {}", expression)
+        } else {
+            expression.to_owned()
+        };
+        let schedule = cron::Schedule::from_str(&expression)?;
+        Ok(Schedule(Kind::Cron(Box::new(schedule))))
+    }
+
+    /// First occurrence strictly after `after`, if the schedule has any left.
+    fn next_after(&self, after: Occurrence) -> Option<Occurrence> {
+        match &self.0 {
+            Kind::Interval(period) => Some(Occurrence {
+                at: after.at + *period,
+                wall: after.wall.checked_add_signed(TimeDelta::from_std(*period).ok()?)?,
+            }),
+            Kind::Cron(schedule) => {
+                let wall = schedule.after(&after.wall).next()?;
+                let delay = (wall - Utc::now()).to_std().unwrap_or_default();
+                Some(Occurrence {
+                    at: Instant::now() + delay,
+                    wall,
+                })
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Kind::Interval(period) => write!(f, "every {:?}", period),
+            Kind::Cron(schedule) => write!(f, "cron {}", schedule),
+        }
+    }
+}
+
+/// A point on a schedule. Cron occurrences are computed on the wall clock and slept for on the
+/// monotonic one, so both are kept to avoid firing the same occurrence twice.
+#[derive(Debug, Clone, Copy)]
+struct Occurrence {
+    at: Instant,
+    wall: DateTime<Utc>,
+}
+
+impl Occurrence {
+    fn now() -> Self {
+        Occurrence {
+            at: Instant::now(),
+            wall: Utc::now(),
+        }
+    }
+}
+
+/// A named unit of periodic work. Runs of the same job never overlap: an occurrence that comes
+/// due while the previous run is still going is handled by the missed-tick behavior.
+pub struct Job {
+    name: String,
+    schedule: Schedule,
+    missed_tick_behavior: MissedTickBehavior,
+    jitter: Duration,
+    timeout: Option<Duration>,
+    task: JobFn,
+}
+
+impl Job {
+    pub fn new<F, Fut>(name: impl Into<String>, schedule: Schedule, task: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ServiceResult> + Send + 'static,
+    {
+        Job {
+            name: name.into(),
+            schedule,
+            missed_tick_behavior: MissedTickBehavior::Skip,
+            jitter: Duration::ZERO,
+            timeout: None,
+            task: Arc::new(move || Box::pin(task())),
+        }
+    }
+
+    /// What to do about occurrences missed while a run overran: `Burst` catches up on all of
+    /// them, `Delay` runs once right away and restarts the schedule from there, `Skip` (the
+    /// default) waits for the next occurrence.
+    pub fn with_missed_tick_behavior(mut self, behavior: MissedTickBehavior) -> Self {
+        self.missed_tick_behavior = behavior;
+        self
+    }
+
+    /// Delays every run by a random duration up to `jitter`, to spread load across instances.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Aborts a run that takes longer than `timeout`; the run counts as failed.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// When the run after the one due at `due` should start, given that the last run ended now.
+    fn next_due(&self, due: Occurrence) -> Option<Occurrence> {
+        let now = Occurrence::now();
+        let next = self.schedule.next_after(due)?;
+        if next.at > now.at {
+            return Some(next);
+        }
+        match self.missed_tick_behavior {
+            MissedTickBehavior::Burst => Some(next),
+            MissedTickBehavior::Delay => Some(now),
+            _ => self.schedule.next_after(now),
+        }
+    }
+
+    fn jitter(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return Duration::ZERO;
+        }
+        self.jitter.mul_f64(rand::rng().random_range(0.0..=1.0))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Outcome {
+    Success,
+    Failure,
+    Timeout,
+    Panic,
+}
+
+impl Outcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Failure => "failure",
+            Outcome::Timeout => "timeout",
+            Outcome::Panic => "panic",
+        }
+    }
+}
+
+#[derive(Clone)]
+struct JobMetrics {
+    runs: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+impl JobMetrics {
+    fn new(meter: &Meter) -> Self {
+        JobMetrics {
+            runs: meter
+                .u64_counter("scheduler.job.runs")
+                .with_description("Number of scheduled job runs by outcome")
+                .with_unit("{run}")
+                .build(),
+            duration: meter
+                .f64_histogram("scheduler.job.duration")
+                .with_description("Duration of scheduled job runs")
+                .with_unit("s")
+                .build(),
+        }
+    }
+
+    fn record(&self, job: &str, outcome: Outcome, duration: Duration) {
+        let attributes = [
+            KeyValue::new(JOB_NAME, job.to_owned()),
+            KeyValue::new(JOB_OUTCOME, outcome.as_str()),
+        ];
+        self.runs.add(1, &attributes);
+        self.duration.record(duration.as_secs_f64(), &attributes);
+    }
+}
+
+/// Runs a set of jobs on their schedules until shutdown.
+///
+/// Once shutdown drains the scheduler no new runs start, while runs in progress may finish;
+/// they are aborted when shutdown is cancelled.
+pub struct Scheduler {
+    name: String,
+    jobs: Vec<Arc<Job>>,
+    metrics: JobMetrics,
+}
+
+impl Scheduler {
+    pub fn new(meter: &Meter) -> Self {
+        Scheduler {
+            name: "scheduler".to_owned(),
+            jobs: vec![],
+            metrics: JobMetrics::new(meter),
+        }
+    }
+
+    /// Service name, for running more than one scheduler under a supervisor.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn with_job(mut self, job: Job) -> Self {
+        assert!(
+            self.jobs.iter().all(|scheduled| scheduled.name != job.name),
+            "job {} is already scheduled",
+            job.name
+        );
+        self.jobs.push(Arc::new(job));
+        self
+    }
+}
+
+#[async_trait]
+impl StarlightService for Scheduler {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn run(&self, shutdown: ShutdownToken) -> ServiceResult {
+        let mut running = JoinSet::new();
+        for job in &self.jobs {
+            running.spawn(run_job(job.clone(), self.metrics.clone(), shutdown.clone()));
+        }
+        while let Some(joined) = running.join_next().await {
+            if let Err(err) = joined {
+                return Err(ServiceError::from(format!("job loop failed: {}", err)));
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn run_job(job: Arc<Job>, metrics: JobMetrics, shutdown: ShutdownToken) {
+    let mut due = job.schedule.next_after(Occurrence::now());
+
+    while let Some(scheduled) = due {
+        let start_at = scheduled.at + job.jitter();
+        tokio::select! {
+            _ = tokio::time::sleep_until(start_at) => {}
+            _ = shutdown.draining() => return,
+        }
+
+        let span = info_span!("scheduled_job", job.name = %job.name, job.outcome = tracing::field::Empty);
+        let started = Instant::now();
+        let mut run = tokio::spawn((job.task)().instrument(span.clone()));
+        let timeout = async {
+            match job.timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let outcome = tokio::select! {
+            joined = &mut run => match joined {
+                Ok(Ok(())) => Outcome::Success,
+                Ok(Err(err)) => {
+                    span.in_scope(|| warn!("job {} failed: {}", job.name, err));
+                    Outcome::Failure
+                }
+                Err(_) => {
+                    span.in_scope(|| error!("job {} panicked", job.name));
+                    Outcome::Panic
+                }
+            },
+            _ = timeout => {
+                run.abort();
+                span.in_scope(|| warn!("job {} did not finish within {:?}", job.name, job.timeout));
+                Outcome::Timeout
+            }
+            _ = shutdown.cancelled() => {
+                run.abort();
+                return;
+            }
+        };
+        span.record("job.outcome", outcome.as_str());
+        metrics.record(&job.name, outcome, started.elapsed());
+
+        due = job.next_due(scheduled);
+    }
+    info!("job {} has no occurrences left", job.name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const PERIOD: Duration = Duration::from_secs(1);
+
+    fn job(behavior: MissedTickBehavior) -> Job {
+        Job::new("test", Schedule::every(PERIOD), || async { Ok(()) })
+            .with_missed_tick_behavior(behavior)
+    }
+
+    /// An occurrence that was due `ago`, as if its run took that long.
+    fn due(ago: Duration) -> Occurrence {
+        Occurrence {
+            at: Instant::now() - ago,
+            wall: Utc::now() - TimeDelta::from_std(ago).unwrap(),
+        }
+    }
+
+    fn next_cron(expression: &str, after: DateTime<Utc>) -> DateTime<Utc> {
+        let schedule = Schedule::cron(expression).unwrap();
+        let after = Occurrence {
+            at: Instant::now(),
+            wall: after,
+        };
+        schedule.next_after(after).unwrap().wall
+    }
+
+    #[test]
+    fn cron_seconds_field_is_optional() {
+        let after = Utc.with_ymd_and_hms(2024, 5, 1, 12, 3, 10).unwrap();
+        let expected = Utc.with_ymd_and_hms(2024, 5, 1, 12, 5, 0).unwrap();
+        assert_eq!(next_cron("*/5 * * * *", after), expected);
+        assert_eq!(next_cron("0 */5 * * * *", after), expected);
+        assert_eq!(
+            next_cron("30 * * * * *", after),
+            Utc.with_ymd_and_hms(2024, 5, 1, 12, 3, 30).unwrap()
+        );
+    }
+
+    #[test]
+    fn cron_rejects_invalid_expressions() {
+        assert!(Schedule::cron("not a schedule").is_err());
+        assert!(Schedule::cron("61 * * * *").is_err());
+        assert!(Schedule::cron("* * *").is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "schedule period must be non-zero")]
+    fn interval_rejects_a_zero_period() {
+        Schedule::every(Duration::ZERO);
+    }
+
+    #[test]
+    fn runs_on_schedule_when_on_time() {
+        for behavior in [
+            MissedTickBehavior::Burst,
+            MissedTickBehavior::Delay,
+            MissedTickBehavior::Skip,
+        ] {
+            let due = due(Duration::ZERO);
+            let next = job(behavior).next_due(due).unwrap();
+            assert_eq!(next.at, due.at + PERIOD, "{:?}", behavior);
+        }
+    }
+
+    #[test]
+    fn burst_catches_up_on_missed_occurrences() {
+        let due = due(PERIOD * 10);
+        let next = job(MissedTickBehavior::Burst).next_due(due).unwrap();
+        assert_eq!(next.at, due.at + PERIOD);
+    }
+
+    #[test]
+    fn delay_runs_right_away_after_an_overrun() {
+        let before = Instant::now();
+        let next = job(MissedTickBehavior::Delay)
+            .next_due(due(PERIOD * 10))
+            .unwrap();
+        assert!(next.at >= before && next.at < before + PERIOD / 2);
+    }
+
+    #[test]
+    fn skip_waits_for_the_next_occurrence_after_an_overrun() {
+        let before = Instant::now();
+        let next = job(MissedTickBehavior::Skip)
+            .next_due(due(PERIOD * 10))
+            .unwrap();
+        assert!(next.at >= before + PERIOD && next.at < before + PERIOD * 3 / 2);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::signal::Signal;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+/// Why services are being shut down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShutdownReason {
+    Signal(Signal),
+    /// Shutdown was asked for explicitly, e.g. through an admin endpoint.
+    AdminRequest,
+    /// A time limit set by the application ran out.
+    Deadline,
+    /// The named service returned before shutdown and was not restarted.
+    ServiceExited(String),
+    /// The named service failed and could not be recovered.
+    ServiceFailed { service: String, error: String },
+}
+
+impl fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShutdownReason::Signal(signal) => write!(f, "received {:?}", signal),
+            ShutdownReason::AdminRequest => write!(f, "requested by an admin"),
+            ShutdownReason::Deadline => write!(f, "deadline reached"),
+            ShutdownReason::ServiceExited(service) => write!(f, "service {} exited", service),
+            ShutdownReason::ServiceFailed { service, error } => {
+                write!(f, "service {} failed: {}", service, error)
+            }
+        }
+    }
+}
+
+/// Hierarchical shutdown handle with two phases.
+///
+/// While *draining* a service stops taking new work but finishes what it has in flight; once
+/// *cancelled* it abandons in-flight work too. Cancelling also drains, and both propagate from a
+/// token to all of its children, never the other way round.
+#[derive(Clone)]
+pub struct ShutdownToken {
+    drain: CancellationToken,
+    cancel: CancellationToken,
+    reason: Arc<Mutex<Option<ShutdownReason>>>,
+    parent: Option<Box<ShutdownToken>>,
+    requested: Arc<watch::Sender<Option<ShutdownReason>>>,
+}
+
+impl Default for ShutdownToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ShutdownToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShutdownToken")
+            .field("draining", &self.is_draining())
+            .field("cancelled", &self.is_cancelled())
+            .field("reason", &self.reason())
+            .finish()
+    }
+}
+
+impl ShutdownToken {
+    pub fn new() -> Self {
+        let (requested, _) = watch::channel(None);
+        ShutdownToken {
+            drain: CancellationToken::new(),
+            cancel: CancellationToken::new(),
+            reason: Arc::new(Mutex::new(None)),
+            parent: None,
+            requested: Arc::new(requested),
+        }
+    }
+
+    /// A token that drains and cancels with this one, but can also be shut down on its own.
+    pub fn child_token(&self) -> Self {
+        ShutdownToken {
+            drain: self.drain.child_token(),
+            cancel: self.cancel.child_token(),
+            reason: Arc::new(Mutex::new(None)),
+            parent: Some(Box::new(self.clone())),
+            requested: self.requested.clone(),
+        }
+    }
+
+    /// Starts the drain phase for this token and its children.
+    pub fn drain(&self, reason: ShutdownReason) {
+        self.set_reason(reason);
+        self.drain.cancel();
+    }
+
+    /// Hard stop for this token and its children; implies draining.
+    pub fn cancel(&self, reason: ShutdownReason) {
+        self.set_reason(reason);
+        self.drain.cancel();
+        self.cancel.cancel();
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.drain.is_cancelled()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Resolves once draining starts, or immediately if it already has.
+    pub async fn draining(&self) {
+        self.drain.cancelled().await
+    }
+
+    /// Resolves once the hard stop starts, or immediately if it already has.
+    pub async fn cancelled(&self) {
+        self.cancel.cancelled().await
+    }
+
+    /// Reason given to this token, or to the closest ancestor that was shut down.
+    pub fn reason(&self) -> Option<ShutdownReason> {
+        let reason = self.reason.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        reason.or_else(|| self.parent.as_ref().and_then(|parent| parent.reason()))
+    }
+
+    /// Asks the owner of the token tree, normally the `Supervisor`, to shut everything down.
+    /// Only the first request is kept.
+    pub fn request_shutdown(&self, reason: ShutdownReason) {
+        self.requested.send_if_modified(|requested| {
+            if requested.is_some() {
+                return false;
+            }
+            *requested = Some(reason);
+            true
+        });
+    }
+
+    /// Resolves with the first reason passed to `request_shutdown` anywhere in the token tree.
+    pub async fn shutdown_requested(&self) -> ShutdownReason {
+        let mut requested = self.requested.subscribe();
+        let reason = requested
+            .wait_for(Option::is_some)
+            .await
+            .expect("the sender lives as long as the token");
+        reason.clone().expect("checked by wait_for")
+    }
+
+    fn set_reason(&self, reason: ShutdownReason) {
+        let mut current = self.reason.lock().unwrap_or_else(PoisonError::into_inner);
+        if current.is_none() {
+            *current = Some(reason);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn draining_comes_before_cancelling() {
+        let shutdown = ShutdownToken::new();
+        shutdown.drain(ShutdownReason::Deadline);
+        shutdown.draining().await;
+        assert!(shutdown.is_draining());
+        assert!(!shutdown.is_cancelled());
+        let cancelled = tokio::time::timeout(Duration::from_millis(20), shutdown.cancelled()).await;
+        assert!(cancelled.is_err());
+
+        shutdown.cancel(ShutdownReason::AdminRequest);
+        shutdown.cancelled().await;
+        assert!(shutdown.is_cancelled());
+        assert_eq!(shutdown.reason(), Some(ShutdownReason::Deadline));
+    }
+
+    #[tokio::test]
+    async fn cancelling_also_drains() {
+        let shutdown = ShutdownToken::new();
+        shutdown.cancel(ShutdownReason::AdminRequest);
+        shutdown.draining().await;
+        assert!(shutdown.is_draining());
+        assert!(shutdown.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn propagates_down_the_parent_chain_only() {
+        let root = ShutdownToken::new();
+        let child = root.child_token();
+        let grandchild = child.child_token();
+
+        child.drain(ShutdownReason::ServiceExited("api".into()));
+        assert!(!root.is_draining());
+        assert!(grandchild.is_draining());
+        assert_eq!(root.reason(), None);
+        assert_eq!(
+            grandchild.reason(),
+            Some(ShutdownReason::ServiceExited("api".into()))
+        );
+
+        root.cancel(ShutdownReason::Deadline);
+        grandchild.cancelled().await;
+        assert!(child.is_cancelled());
+        assert_eq!(root.reason(), Some(ShutdownReason::Deadline));
+        assert_eq!(
+            grandchild.reason(),
+            Some(ShutdownReason::ServiceExited("api".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_the_first_shutdown_request_from_anywhere_in_the_tree() {
+        let root = ShutdownToken::new();
+        let child = root.child_token().child_token();
+
+        child.request_shutdown(ShutdownReason::AdminRequest);
+        root.request_shutdown(ShutdownReason::Deadline);
+        assert_eq!(
+            root.shutdown_requested().await,
+            ShutdownReason::AdminRequest
+        );
+        assert_eq!(
+            child.shutdown_requested().await,
+            ShutdownReason::AdminRequest
+        );
+        assert!(
+            !root.is_draining(),
+            "requesting shutdown leaves draining to the owner"
+        );
+    }
+}
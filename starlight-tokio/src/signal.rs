@@ -0,0 +1,143 @@
+use crate::shutdown::{ShutdownReason, ShutdownToken};
+use std::io;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// SIGTERM
+    Terminate,
+    /// SIGINT, or Ctrl-C on non-unix platforms
+    Interrupt,
+    /// SIGHUP
+    Hangup,
+}
+
+impl Signal {
+    /// Conventional `128 + signal number` exit code, used when a second signal forces the exit.
+    fn exit_code(&self) -> i32 {
+        match self {
+            Signal::Hangup => 129,
+            Signal::Interrupt => 130,
+            Signal::Terminate => 143,
+        }
+    }
+}
+
+/// Listens for process signals for the lifetime of the runtime.
+///
+/// The first SIGTERM or SIGINT requests a graceful shutdown, a second one exits the process
+/// immediately. SIGHUP never stops the process; it is published as a reload event instead.
+pub struct SignalListener {
+    shutdown_rx: watch::Receiver<Option<Signal>>,
+    reload_rx: watch::Receiver<u64>,
+}
+
+impl SignalListener {
+    /// Starts listening. Must be called from within a tokio runtime.
+    ///
+    /// The handlers are registered before this returns, so from then on a signal can no longer
+    /// kill the process with its default action.
+    pub fn install() -> io::Result<Self> {
+        let mut signals = OsSignals::new()?;
+        let (shutdown_tx, shutdown_rx) = watch::channel(None);
+        let (reload_tx, reload_rx) = watch::channel(0);
+
+        tokio::spawn(async move {
+            loop {
+                let signal = signals.recv().await;
+                match signal {
+                    Signal::Hangup => {
+                        info!("received {:?}, publishing reload event", signal);
+                        reload_tx.send_modify(|generation| *generation += 1);
+                    }
+                    _ if shutdown_tx.borrow().is_some() => {
+                        warn!("received {:?} during graceful shutdown, exiting immediately", signal);
+                        std::process::exit(signal.exit_code());
+                    }
+                    _ => {
+                        info!("received {:?}, starting graceful shutdown", signal);
+                        let _ = shutdown_tx.send(Some(signal));
+                    }
+                }
+            }
+        });
+
+        Ok(SignalListener {
+            shutdown_rx,
+            reload_rx,
+        })
+    }
+
+    /// Resolves with the first SIGTERM/SIGINT received.
+    pub async fn shutdown_signal(&self) -> Signal {
+        let mut shutdown_rx = self.shutdown_rx.clone();
+        let signal = shutdown_rx
+            .wait_for(Option::is_some)
+            .await
+            .expect("signal listener stopped");
+        signal.expect("checked by wait_for")
+    }
+
+    /// Changes every time SIGHUP is received; await `changed()` to react to reload requests.
+    pub fn reload_receiver(&self) -> watch::Receiver<u64> {
+        self.reload_rx.clone()
+    }
+
+    /// Drains `shutdown` when the first SIGTERM/SIGINT arrives, for use without a `Supervisor`.
+    pub fn forward_shutdown(&self, shutdown: ShutdownToken) -> JoinHandle<()> {
+        let mut shutdown_rx = self.shutdown_rx.clone();
+        tokio::spawn(async move {
+            if let Ok(signal) = shutdown_rx.wait_for(Option::is_some).await
+                && let Some(signal) = *signal
+            {
+                shutdown.drain(ShutdownReason::Signal(signal));
+            }
+        })
+    }
+}
+
+#[cfg(unix)]
+struct OsSignals {
+    terminate: tokio::signal::unix::Signal,
+    interrupt: tokio::signal::unix::Signal,
+    hangup: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl OsSignals {
+    fn new() -> io::Result<Self> {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        Ok(OsSignals {
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+            hangup: signal(SignalKind::hangup())?,
+        })
+    }
+
+    async fn recv(&mut self) -> Signal {
+        tokio::select! {
+            _ = self.terminate.recv() => Signal::Terminate,
+            _ = self.interrupt.recv() => Signal::Interrupt,
+            _ = self.hangup.recv() => Signal::Hangup,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct OsSignals;
+
+#[cfg(not(unix))]
+impl OsSignals {
+    fn new() -> io::Result<Self> {
+        Ok(OsSignals)
+    }
+
+    async fn recv(&mut self) -> Signal {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for Ctrl-C");
+        Signal::Interrupt
+    }
+}
@@ -0,0 +1,1026 @@
+use crate::StarlightService;
+use crate::health::{Health, HealthCheck, HealthRegistry, HealthStatus};
+use crate::runnable_service::ServiceMetadata;
+use crate::shutdown::{ShutdownReason, ShutdownToken};
+use crate::signal::SignalListener;
+use crate::restart::{RestartDecision, RestartPolicy, RestartTracker};
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use async_trait::async_trait;
+use tokio::sync::{mpsc, watch};
+use tokio::task::{AbortHandle, Id, JoinError, JoinSet};
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServiceExit {
+    Completed,
+    /// Shutdown began while the service was still waiting for its dependencies.
+    NotStarted,
+    /// `start`, `run` or `stop` returned an error.
+    Failed(String),
+    Panicked(String),
+    Cancelled,
+    /// Still running after both its drain and stop timeouts ran out, so it was aborted.
+    TimedOut,
+}
+
+impl ServiceExit {
+    pub fn is_failure(&self) -> bool {
+        !matches!(self, ServiceExit::Completed | ServiceExit::NotStarted)
+    }
+}
+
+impl fmt::Display for ServiceExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceExit::Completed => write!(f, "completed"),
+            ServiceExit::NotStarted => write!(f, "not started"),
+            ServiceExit::Failed(error) => write!(f, "failed: {}", error),
+            ServiceExit::Panicked(message) => write!(f, "panicked: {}", message),
+            ServiceExit::Cancelled => write!(f, "cancelled"),
+            ServiceExit::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServiceReport {
+    pub name: String,
+    pub metadata: ServiceMetadata,
+    pub exit: ServiceExit,
+    pub restarts: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct SupervisorReport {
+    pub reason: ShutdownReason,
+    pub services: Vec<ServiceReport>,
+}
+
+impl SupervisorReport {
+    /// True when shutdown was not caused by a service and every service stopped on its own.
+    pub fn is_clean(&self) -> bool {
+        !matches!(
+            self.reason,
+            ShutdownReason::ServiceExited(_) | ShutdownReason::ServiceFailed { .. }
+        ) && self
+            .services
+            .iter()
+            .all(|service| !service.exit.is_failure())
+    }
+}
+
+impl fmt::Display for SupervisorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "shut down because {}", self.reason)?;
+        for service in &self.services {
+            write!(
+                f,
+                "; {}: {} after {} restarts",
+                service.name, service.exit, service.restarts
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a supervisor could not run its services at all.
+#[derive(Debug)]
+pub enum SupervisorError {
+    /// Services depending on services that were never registered, as `(service, dependency)`.
+    UnknownDependencies(Vec<(String, String)>),
+    /// Listening for process signals failed, see [`SignalListener::install`].
+    Signals(io::Error),
+}
+
+impl fmt::Display for SupervisorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SupervisorError::UnknownDependencies(missing) => {
+                let missing = missing
+                    .iter()
+                    .map(|(service, dependency)| format!("{} -> {}", service, dependency))
+                    .collect::<Vec<_>>();
+                write!(f, "services depend on unregistered services: {}", missing.join(", "))
+            }
+            SupervisorError::Signals(err) => write!(f, "failed to listen for signals: {}", err),
+        }
+    }
+}
+
+impl Error for SupervisorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SupervisorError::Signals(err) => Some(err),
+            SupervisorError::UnknownDependencies(_) => None,
+        }
+    }
+}
+
+type ReloadHook = Arc<dyn Fn() + Send + Sync>;
+
+/// Per-service settings; anything left unset falls back to the supervisor's defaults.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceOptions {
+    grace_period: Option<Duration>,
+    stop_timeout: Option<Duration>,
+    restart_policy: RestartPolicy,
+    dependencies: Vec<String>,
+}
+
+impl ServiceOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = Some(grace_period);
+        self
+    }
+
+    pub fn with_stop_timeout(mut self, stop_timeout: Duration) -> Self {
+        self.stop_timeout = Some(stop_timeout);
+        self
+    }
+
+    pub fn with_restart_policy(mut self, restart_policy: RestartPolicy) -> Self {
+        self.restart_policy = restart_policy;
+        self
+    }
+
+    /// Starts the service only once the named service is ready, and stops it before that one.
+    pub fn with_dependency(mut self, name: impl Into<String>) -> Self {
+        self.dependencies.push(name.into());
+        self
+    }
+}
+
+struct RegisteredService {
+    name: String,
+    metadata: ServiceMetadata,
+    service: Arc<dyn StarlightService>,
+    grace_period: Duration,
+    stop_timeout: Duration,
+    restart_policy: RestartPolicy,
+    dependencies: Vec<String>,
+}
+
+struct ServiceState {
+    launched: bool,
+    exit: Option<ServiceExit>,
+    restart_at: Option<Instant>,
+    abort_handle: Option<AbortHandle>,
+    restarts: RestartTracker,
+    /// Bumped on every start so readiness reported by an earlier run is ignored.
+    generation: u64,
+    ready: bool,
+    shutdown: ShutdownToken,
+    drain_started_at: Option<Instant>,
+    cancelled_at: Option<Instant>,
+    /// Lifecycle part of the service's health, shared with its health check.
+    lifecycle: Arc<Mutex<Health>>,
+}
+
+impl ServiceState {
+    fn new(shutdown: ShutdownToken) -> Self {
+        ServiceState {
+            launched: false,
+            exit: None,
+            restart_at: None,
+            abort_handle: None,
+            restarts: RestartTracker::default(),
+            generation: 0,
+            ready: false,
+            shutdown,
+            drain_started_at: None,
+            cancelled_at: None,
+            lifecycle: Arc::new(Mutex::new(Health::unhealthy("waiting for dependencies"))),
+        }
+    }
+
+    fn set_health(&self, health: Health) {
+        *self.lifecycle.lock().unwrap_or_else(PoisonError::into_inner) = health;
+    }
+
+    fn is_running(&self) -> bool {
+        self.launched && self.exit.is_none()
+    }
+}
+
+/// Indices of the services each service is linked to.
+type Edges = Vec<Vec<usize>>;
+
+/// Sent by a service's lifecycle task once its `ready` check resolves.
+struct Readiness {
+    index: usize,
+    generation: u64,
+    result: Result<(), String>,
+}
+
+/// Runs a set of services, starting each once its dependencies are ready and stopping them in
+/// reverse dependency order.
+///
+/// Every service gets a child of the supervisor's [`ShutdownToken`]. On shutdown the service is
+/// drained first and only cancelled once its grace period runs out; if it still has not
+/// returned after the stop timeout, its task is aborted.
+pub struct Supervisor {
+    services: Vec<RegisteredService>,
+    grace_period: Duration,
+    stop_timeout: Duration,
+    shutdown: ShutdownToken,
+    ready_tx: watch::Sender<bool>,
+    health: Option<HealthRegistry>,
+    reload_hook: Option<ReloadHook>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        let (ready_tx, _) = watch::channel(false);
+        Supervisor {
+            services: vec![],
+            grace_period: Duration::from_secs(30),
+            stop_timeout: Duration::from_secs(5),
+            shutdown: ShutdownToken::new(),
+            ready_tx,
+            health: None,
+            reload_hook: None,
+        }
+    }
+
+    /// Default time each service gets to drain before it is cancelled.
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Registers a health check per service and fails readiness in `health` once shutdown starts.
+    pub fn with_health_registry(mut self, health: HealthRegistry) -> Self {
+        self.health = Some(health);
+        self
+    }
+
+    /// Called by [`Supervisor::run`] on every SIGHUP, e.g. to reload configuration.
+    pub fn with_reload_hook(mut self, reload_hook: impl Fn() + Send + Sync + 'static) -> Self {
+        self.reload_hook = Some(Arc::new(reload_hook));
+        self
+    }
+
+    /// Default time a cancelled service gets to return before its task is aborted.
+    pub fn with_stop_timeout(mut self, stop_timeout: Duration) -> Self {
+        self.stop_timeout = stop_timeout;
+        self
+    }
+
+    pub fn register(&mut self, service: impl StarlightService) -> &mut Self {
+        self.register_with(service, ServiceOptions::default())
+    }
+
+    pub fn register_with(
+        &mut self,
+        service: impl StarlightService,
+        options: ServiceOptions,
+    ) -> &mut Self {
+        let name = service.name().to_owned();
+        assert!(
+            self.services.iter().all(|registered| registered.name != name),
+            "service {} is already registered",
+            name
+        );
+        if let Some(cycle) = self.find_cycle(&name, &options.dependencies) {
+            panic!("dependency cycle between services: {}", cycle.join(" -> "));
+        }
+        self.services.push(RegisteredService {
+            name,
+            metadata: service.metadata(),
+            service: Arc::new(service),
+            grace_period: options.grace_period.unwrap_or(self.grace_period),
+            stop_timeout: options.stop_timeout.unwrap_or(self.stop_timeout),
+            restart_policy: options.restart_policy,
+            dependencies: options.dependencies,
+        });
+        self
+    }
+
+    /// The graph is acyclic before `name` is added, so any new cycle has to pass through it.
+    fn find_cycle(&self, name: &str, dependencies: &[String]) -> Option<Vec<String>> {
+        let mut path = vec![name.to_owned()];
+        let mut pending = dependencies
+            .iter()
+            .rev()
+            .map(|dependency| (dependency.as_str(), 1))
+            .collect::<Vec<_>>();
+        let mut visited = HashSet::new();
+
+        while let Some((current, depth)) = pending.pop() {
+            path.truncate(depth);
+            path.push(current.to_owned());
+            if current == name {
+                return Some(path);
+            }
+            if !visited.insert(current) {
+                continue;
+            }
+            if let Some(registered) = self.services.iter().find(|r| r.name == current) {
+                pending.extend(
+                    registered
+                        .dependencies
+                        .iter()
+                        .rev()
+                        .map(|dependency| (dependency.as_str(), depth + 1)),
+                );
+            }
+        }
+        None
+    }
+
+    /// Indices of each service's dependencies and of the services depending on it. Services
+    /// can be registered in any order, so unknown dependencies are only reported here.
+    fn dependency_graph(&self) -> Result<(Edges, Edges), SupervisorError> {
+        let mut dependencies = vec![vec![]; self.services.len()];
+        let mut dependents = vec![vec![]; self.services.len()];
+        let mut missing = vec![];
+        for (index, registered) in self.services.iter().enumerate() {
+            for dependency in &registered.dependencies {
+                match self.services.iter().position(|r| &r.name == dependency) {
+                    Some(dependency_index) => {
+                        dependencies[index].push(dependency_index);
+                        dependents[dependency_index].push(index);
+                    }
+                    None => missing.push((registered.name.clone(), dependency.clone())),
+                }
+            }
+        }
+        if missing.is_empty() {
+            Ok((dependencies, dependents))
+        } else {
+            Err(SupervisorError::UnknownDependencies(missing))
+        }
+    }
+
+    /// Root of the services' tokens; call `request_shutdown` on it, or on any service's token,
+    /// to shut the supervisor down.
+    pub fn shutdown_token(&self) -> ShutdownToken {
+        self.shutdown.clone()
+    }
+
+    /// Turns `true` once every service reported ready, and back to `false` while any service
+    /// restarts or once shutdown starts.
+    pub fn readiness(&self) -> watch::Receiver<bool> {
+        self.ready_tx.subscribe()
+    }
+
+    /// Runs every service until one of them exits for good, a service requests shutdown, or
+    /// SIGTERM/SIGINT arrives. SIGHUP calls the reload hook. Fails without starting anything if
+    /// a dependency is not registered or signals cannot be listened for.
+    pub async fn run(self) -> Result<SupervisorReport, SupervisorError> {
+        self.dependency_graph()?;
+        let signals = SignalListener::install().map_err(SupervisorError::Signals)?;
+        let mut reload_rx = signals.reload_receiver();
+        let reload_hook = self.reload_hook.clone();
+        self.run_until(async move {
+            loop {
+                tokio::select! {
+                    signal = signals.shutdown_signal() => break ShutdownReason::Signal(signal),
+                    Ok(()) = reload_rx.changed() => match &reload_hook {
+                        Some(reload_hook) => reload_hook(),
+                        None => info!("no reload hook is registered, ignoring the reload request"),
+                    },
+                }
+            }
+        })
+        .await
+    }
+
+    /// Like [`Supervisor::run`], with `shutdown` resolving when the process is asked to stop.
+    pub async fn run_until(
+        self,
+        shutdown: impl Future<Output = ShutdownReason>,
+    ) -> Result<SupervisorReport, SupervisorError> {
+        let (readiness_tx, mut readiness_rx) = mpsc::unbounded_channel();
+        let (dependencies, dependents) = self.dependency_graph()?;
+        let mut running = Running::default();
+        let mut states = self
+            .services
+            .iter()
+            .map(|_| ServiceState::new(self.shutdown.child_token()))
+            .collect::<Vec<_>>();
+
+        if let Some(health) = &self.health {
+            for (registered, state) in self.services.iter().zip(&states) {
+                health.register(
+                    registered.name.clone(),
+                    ServiceHealthCheck {
+                        service: registered.service.clone(),
+                        lifecycle: state.lifecycle.clone(),
+                    },
+                );
+            }
+        }
+
+        self.start_unblocked(&mut states, &dependencies, &mut running, &readiness_tx);
+
+        tokio::pin!(shutdown);
+        let reason = loop {
+            let next_restart = states.iter().filter_map(|state| state.restart_at).min();
+
+            tokio::select! {
+                reason = &mut shutdown => break reason,
+                reason = self.shutdown.shutdown_requested() => break reason,
+                Some(readiness) = readiness_rx.recv() => {
+                    let registered = &self.services[readiness.index];
+                    let state = &mut states[readiness.index];
+                    if state.generation != readiness.generation || !state.is_running() {
+                        continue;
+                    }
+                    match readiness.result {
+                        Ok(()) => {
+                            info!("service {} is ready", registered.name);
+                            state.ready = true;
+                            state.set_health(Health::healthy());
+                            self.publish_readiness(&states);
+                            self.start_unblocked(&mut states, &dependencies, &mut running, &readiness_tx);
+                        }
+                        Err(err) => {
+                            error!("service {} failed its readiness check: {}", registered.name, err);
+                            state.set_health(Health::unhealthy(format!("readiness check failed: {}", err)));
+                            break ShutdownReason::ServiceFailed {
+                                service: registered.name.clone(),
+                                error: format!("readiness check failed: {}", err),
+                            };
+                        }
+                    }
+                }
+                Some((index, exit)) = running.join_next() => {
+                    let registered = &self.services[index];
+                    let state = &mut states[index];
+                    let restart = registered.restart_policy.applies_to(exit.is_failure());
+                    state.set_health(Health::unhealthy(exit.to_string()));
+                    state.exit = Some(exit);
+                    state.ready = false;
+                    self.publish_readiness(&states);
+                    let state = &mut states[index];
+
+                    let exit = state.exit.as_ref().expect("set above");
+
+                    if !restart && exit.is_failure() {
+                        break ShutdownReason::ServiceFailed {
+                            service: registered.name.clone(),
+                            error: exit.to_string(),
+                        };
+                    }
+                    if !restart {
+                        break ShutdownReason::ServiceExited(registered.name.clone());
+                    }
+                    match state.restarts.next(&registered.restart_policy) {
+                        RestartDecision::After(delay) => {
+                            warn!(
+                                "service {} {}, restarting in {:?}",
+                                registered.name, exit, delay
+                            );
+                            state.restart_at = Some(Instant::now() + delay);
+                        }
+                        RestartDecision::BudgetExhausted => {
+                            error!("service {} exhausted its restart budget", registered.name);
+                            break ShutdownReason::ServiceFailed {
+                                service: registered.name.clone(),
+                                error: format!("restart budget exhausted, last {}", exit),
+                            };
+                        }
+                    }
+                }
+                _ = sleep_until(next_restart) => {
+                    let now = Instant::now();
+                    for (index, state) in states.iter_mut().enumerate() {
+                        if state.restart_at.is_some_and(|restart_at| restart_at <= now) {
+                            info!("restarting service {}", self.services[index].name);
+                            self.start(index, state, &mut running, &readiness_tx);
+                        }
+                    }
+                }
+            }
+        };
+
+        info!("shutting down services: {}", reason);
+        if let Some(health) = &self.health {
+            health.mark_shutting_down();
+        }
+        for state in &mut states {
+            state.ready = false;
+            state.restart_at = None;
+            if !state.launched {
+                state.exit = Some(ServiceExit::NotStarted);
+            }
+        }
+        self.publish_readiness(&states);
+
+        loop {
+            // A service starts draining once everything depending on it has stopped.
+            let now = Instant::now();
+            for index in 0..states.len() {
+                let unblocked = dependents[index]
+                    .iter()
+                    .all(|&dependent| !states[dependent].is_running());
+                let state = &mut states[index];
+                if state.is_running() && state.drain_started_at.is_none() && unblocked {
+                    info!("draining service {}", self.services[index].name);
+                    state.drain_started_at = Some(now);
+                    state.set_health(Health::unhealthy("draining"));
+                    state.shutdown.drain(reason.clone());
+                }
+            }
+
+            let next_deadline = self
+                .services
+                .iter()
+                .zip(&states)
+                .filter(|(_, state)| state.is_running())
+                .filter_map(|(registered, state)| phase_deadline(registered, state))
+                .min();
+            let Some(next_deadline) = next_deadline else {
+                break;
+            };
+
+            tokio::select! {
+                joined = running.join_next() => match joined {
+                    Some((index, exit)) => {
+                        if states[index].is_running() {
+                            states[index].exit = Some(exit);
+                        }
+                    }
+                    None => break,
+                },
+                _ = tokio::time::sleep_until(next_deadline) => {
+                    let now = Instant::now();
+                    for (registered, state) in self.services.iter().zip(&mut states) {
+                        let expired = phase_deadline(registered, state)
+                            .is_some_and(|deadline| deadline <= now);
+                        if !state.is_running() || !expired {
+                            continue;
+                        }
+                        if state.cancelled_at.is_none() {
+                            warn!("service {} did not drain within {:?}, cancelling it", registered.name, registered.grace_period);
+                            state.cancelled_at = Some(now);
+                            state.shutdown.cancel(reason.clone());
+                        } else {
+                            warn!("service {} did not stop within {:?} of being cancelled", registered.name, registered.stop_timeout);
+                            if let Some(abort_handle) = &state.abort_handle {
+                                abort_handle.abort();
+                            }
+                            state.exit = Some(ServiceExit::TimedOut);
+                        }
+                    }
+                }
+            }
+        }
+
+        let services = self
+            .services
+            .iter()
+            .zip(states)
+            .map(|(registered, state)| ServiceReport {
+                name: registered.name.clone(),
+                metadata: registered.metadata.clone(),
+                exit: state.exit.unwrap_or(ServiceExit::Cancelled),
+                restarts: state.restarts.total(),
+            })
+            .collect();
+
+        Ok(SupervisorReport { reason, services })
+    }
+
+    fn start(
+        &self,
+        index: usize,
+        state: &mut ServiceState,
+        running: &mut Running,
+        readiness_tx: &mpsc::UnboundedSender<Readiness>,
+    ) {
+        state.launched = true;
+        state.exit = None;
+        state.restart_at = None;
+        state.ready = false;
+        state.generation += 1;
+        state.set_health(Health::unhealthy("starting"));
+
+        let task = lifecycle(
+            index,
+            state.generation,
+            self.services[index].service.clone(),
+            state.shutdown.clone(),
+            readiness_tx.clone(),
+        );
+        state.abort_handle = Some(running.spawn(index, task));
+    }
+
+    /// Starts every service that has not run yet and whose dependencies are all ready.
+    fn start_unblocked(
+        &self,
+        states: &mut [ServiceState],
+        dependencies: &[Vec<usize>],
+        running: &mut Running,
+        readiness_tx: &mpsc::UnboundedSender<Readiness>,
+    ) {
+        for index in 0..states.len() {
+            let unblocked = dependencies[index]
+                .iter()
+                .all(|&dependency| states[dependency].ready);
+            if !states[index].launched && unblocked {
+                info!("starting service {}", self.services[index].name);
+                self.start(index, &mut states[index], running, readiness_tx);
+            }
+        }
+    }
+
+    fn publish_readiness(&self, states: &[ServiceState]) {
+        let all_ready = states.iter().all(|state| state.ready);
+        self.ready_tx.send_if_modified(|ready| {
+            let changed = *ready != all_ready;
+            *ready = all_ready;
+            changed
+        });
+    }
+}
+
+/// Reports the supervisor's view of a service until it is ready, then the service's own health.
+struct ServiceHealthCheck {
+    service: Arc<dyn StarlightService>,
+    lifecycle: Arc<Mutex<Health>>,
+}
+
+#[async_trait]
+impl HealthCheck for ServiceHealthCheck {
+    async fn check(&self) -> Health {
+        let lifecycle = self
+            .lifecycle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        match lifecycle.status {
+            HealthStatus::Healthy => self.service.health().await,
+            _ => lifecycle,
+        }
+    }
+}
+
+/// Lifecycle tasks keyed by task id, so a panicking task can still be traced to its service.
+#[derive(Default)]
+struct Running {
+    tasks: JoinSet<ServiceExit>,
+    services: HashMap<Id, usize>,
+}
+
+impl Running {
+    fn spawn(
+        &mut self,
+        index: usize,
+        task: impl Future<Output = ServiceExit> + Send + 'static,
+    ) -> AbortHandle {
+        let abort_handle = self.tasks.spawn(task);
+        self.services.insert(abort_handle.id(), index);
+        abort_handle
+    }
+
+    async fn join_next(&mut self) -> Option<(usize, ServiceExit)> {
+        let (id, exit) = match self.tasks.join_next_with_id().await? {
+            Ok((id, exit)) => (id, exit),
+            Err(err) => (err.id(), join_error_exit(err)),
+        };
+        let index = self
+            .services
+            .remove(&id)
+            .expect("every task is spawned through Running::spawn");
+        Some((index, exit))
+    }
+}
+
+/// `start` → `run` alongside `ready` → `stop`, reduced to how the service exited.
+async fn lifecycle(
+    index: usize,
+    generation: u64,
+    service: Arc<dyn StarlightService>,
+    shutdown: ShutdownToken,
+    readiness_tx: mpsc::UnboundedSender<Readiness>,
+) -> ServiceExit {
+    if let Err(err) = service.start().await {
+        return ServiceExit::Failed(format!("start failed: {}", err));
+    }
+
+    let run = service.run(shutdown);
+    tokio::pin!(run);
+    let result = tokio::select! {
+        result = &mut run => result,
+        ready = service.ready() => {
+            let _ = readiness_tx.send(Readiness {
+                index,
+                generation,
+                result: ready.map_err(|err| err.to_string()),
+            });
+            run.await
+        }
+    };
+
+    let stopped = service.stop().await;
+    match (result, stopped) {
+        (Err(err), _) => ServiceExit::Failed(err.to_string()),
+        (Ok(()), Err(err)) => ServiceExit::Failed(format!("stop failed: {}", err)),
+        (Ok(()), Ok(())) => ServiceExit::Completed,
+    }
+}
+
+/// When the current shutdown phase of a service runs out, if it has started shutting down.
+fn phase_deadline(registered: &RegisteredService, state: &ServiceState) -> Option<Instant> {
+    match (state.drain_started_at, state.cancelled_at) {
+        (_, Some(cancelled_at)) => Some(cancelled_at + registered.stop_timeout),
+        (Some(drain_started_at), None) => Some(drain_started_at + registered.grace_period),
+        (None, None) => None,
+    }
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+fn join_error_exit(err: JoinError) -> ServiceExit {
+    if err.is_panic() {
+        ServiceExit::Panicked(panic_message(err.into_panic()))
+    } else {
+        ServiceExit::Cancelled
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "non-string panic payload".to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServiceResult;
+
+    /// Records its lifecycle into a log shared between services.
+    struct Recorder {
+        name: &'static str,
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Recorder {
+        fn record(&self, event: &str) {
+            let mut events = self.events.lock().unwrap();
+            events.push(format!("{} {}", event, self.name));
+        }
+    }
+
+    #[async_trait]
+    impl StarlightService for Recorder {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn start(&self) -> ServiceResult {
+            self.record("start");
+            Ok(())
+        }
+
+        async fn run(&self, shutdown: ShutdownToken) -> ServiceResult {
+            shutdown.draining().await;
+            Ok(())
+        }
+
+        async fn stop(&self) -> ServiceResult {
+            self.record("stop");
+            Ok(())
+        }
+    }
+
+    fn recorder(name: &'static str, events: &Arc<Mutex<Vec<String>>>) -> Recorder {
+        Recorder {
+            name,
+            events: events.clone(),
+        }
+    }
+
+    fn depending_on(dependencies: &[&str]) -> ServiceOptions {
+        dependencies
+            .iter()
+            .fold(ServiceOptions::new(), |options, dependency| {
+                options.with_dependency(*dependency)
+            })
+    }
+
+    #[test]
+    #[should_panic(expected = "dependency cycle between services: b -> a -> b")]
+    fn rejects_a_dependency_cycle() {
+        let events = Arc::default();
+        let mut supervisor = Supervisor::new();
+        supervisor.register_with(recorder("a", &events), depending_on(&["b"]));
+        supervisor.register_with(recorder("b", &events), depending_on(&["a"]));
+    }
+
+    #[test]
+    #[should_panic(expected = "dependency cycle between services: c -> a -> b -> c")]
+    fn rejects_an_indirect_dependency_cycle() {
+        let events = Arc::default();
+        let mut supervisor = Supervisor::new();
+        supervisor.register_with(recorder("a", &events), depending_on(&["b"]));
+        supervisor.register_with(recorder("b", &events), depending_on(&["c"]));
+        supervisor.register_with(recorder("c", &events), depending_on(&["a"]));
+    }
+
+    #[test]
+    #[should_panic(expected = "dependency cycle between services: a -> a")]
+    fn rejects_a_service_depending_on_itself() {
+        let events = Arc::default();
+        Supervisor::new().register_with(recorder("a", &events), depending_on(&["a"]));
+    }
+
+    #[test]
+    fn accepts_shared_dependencies() {
+        let events = Arc::default();
+        let mut supervisor = Supervisor::new();
+        supervisor
+            .register(recorder("db", &events))
+            .register_with(recorder("cache", &events), depending_on(&["db"]))
+            .register_with(recorder("api", &events), depending_on(&["db", "cache"]));
+        assert_eq!(supervisor.find_cycle("worker", &["api".to_owned()]), None);
+    }
+
+    #[tokio::test]
+    async fn starts_in_dependency_order_and_stops_in_reverse() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let mut supervisor = Supervisor::new();
+        supervisor
+            .register_with(recorder("api", &events), depending_on(&["cache", "db"]))
+            .register_with(recorder("cache", &events), depending_on(&["db"]))
+            .register(recorder("db", &events));
+        let mut readiness = supervisor.readiness();
+
+        let report = supervisor
+            .run_until(async move {
+                readiness.wait_for(|ready| *ready).await.unwrap();
+                ShutdownReason::AdminRequest
+            })
+            .await
+            .unwrap();
+
+        assert!(report.is_clean(), "{}", report);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "start db",
+                "start cache",
+                "start api",
+                "stop api",
+                "stop cache",
+                "stop db"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_before_starting_anything_when_a_dependency_is_unknown() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let mut supervisor = Supervisor::new();
+        supervisor
+            .register(recorder("db", &events))
+            .register_with(recorder("api", &events), depending_on(&["db", "cache"]))
+            .register_with(recorder("worker", &events), depending_on(&["queue"]));
+
+        let err = supervisor
+            .run_until(std::future::pending())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            &err,
+            SupervisorError::UnknownDependencies(missing)
+                if missing == &[("api".to_owned(), "cache".to_owned()), ("worker".to_owned(), "queue".to_owned())]
+        ));
+        assert_eq!(
+            err.to_string(),
+            "services depend on unregistered services: api -> cache, worker -> queue"
+        );
+        assert!(events.lock().unwrap().is_empty());
+    }
+
+    /// Ignores draining and only returns once cancelled.
+    struct Stubborn;
+
+    #[async_trait]
+    impl StarlightService for Stubborn {
+        fn name(&self) -> &str {
+            "stubborn"
+        }
+
+        async fn run(&self, shutdown: ShutdownToken) -> ServiceResult {
+            shutdown.cancelled().await;
+            Ok(())
+        }
+    }
+
+    /// Set for the child process `exits_on_a_second_signal` runs.
+    #[cfg(unix)]
+    const SIGNAL_CHILD: &str = "STARLIGHT_SIGNAL_CHILD";
+
+    #[cfg(unix)]
+    fn raise(signal: &str) {
+        let status = std::process::Command::new("kill")
+            .arg(format!("-{}", signal))
+            .arg(std::process::id().to_string())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn forwards_sighup_to_the_reload_hook_and_stops_on_sigterm() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let (reload_tx, mut reload_rx) = mpsc::unbounded_channel();
+        let mut supervisor = Supervisor::new().with_reload_hook(move || {
+            let _ = reload_tx.send(());
+        });
+        supervisor.register(recorder("api", &events));
+        let mut readiness = supervisor.readiness();
+        let run = tokio::spawn(supervisor.run());
+        readiness.wait_for(|ready| *ready).await.unwrap();
+
+        raise("HUP");
+        tokio::time::timeout(Duration::from_secs(5), reload_rx.recv())
+            .await
+            .expect("SIGHUP calls the reload hook")
+            .unwrap();
+        assert!(!run.is_finished());
+
+        raise("TERM");
+        let report = tokio::time::timeout(Duration::from_secs(5), run)
+            .await
+            .expect("SIGTERM shuts the supervisor down")
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            report.reason,
+            ShutdownReason::Signal(crate::Signal::Terminate)
+        ));
+        assert_eq!(*events.lock().unwrap(), ["start api", "stop api"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exits_on_a_second_signal() {
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "supervisor::tests::second_signal_child",
+                "--exact",
+                "--ignored",
+                "--nocapture",
+            ])
+            .env(SIGNAL_CHILD, "1")
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(130));
+    }
+
+    /// Run by `exits_on_a_second_signal` in a process of its own, since it ends with
+    /// `process::exit`.
+    #[cfg(unix)]
+    #[tokio::test]
+    #[ignore]
+    async fn second_signal_child() {
+        if std::env::var_os(SIGNAL_CHILD).is_none() {
+            return;
+        }
+        let mut supervisor = Supervisor::new().with_grace_period(Duration::from_secs(60));
+        supervisor.register(Stubborn);
+        let mut readiness = supervisor.readiness();
+        let run = tokio::spawn(supervisor.run());
+        readiness.wait_for(|ready| *ready).await.unwrap();
+
+        raise("TERM");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!run.is_finished(), "the first signal only drains");
+        raise("INT");
+        let _ = tokio::time::timeout(Duration::from_secs(5), run).await;
+        panic!("the second signal did not exit the process");
+    }
+}
@@ -0,0 +1,578 @@
+use crate::StarlightService;
+use crate::runnable_service::ServiceResult;
+use crate::shutdown::{ShutdownReason, ShutdownToken};
+use async_trait::async_trait;
+use opentelemetry::KeyValue;
+use opentelemetry::metrics::{Counter, Histogram, Meter, UpDownCounter};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::Instrument;
+
+const POOL_NAME: &str = "worker_pool.name";
+const JOB_OUTCOME: &str = "job.outcome";
+
+type JobFuture = Pin<Box<dyn Future<Output = ServiceResult> + Send>>;
+
+/// What `submit` does when the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait for a free slot.
+    Wait,
+    /// Fail with [`SubmitError::Full`].
+    Reject,
+    /// Make room by discarding the job that has been queued the longest.
+    DropOldest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmitError {
+    Full,
+    /// The pool is shutting down, or not running.
+    Closed,
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmitError::Full => write!(f, "worker pool queue is full"),
+            SubmitError::Closed => write!(f, "worker pool is not accepting jobs"),
+        }
+    }
+}
+
+impl Error for SubmitError {}
+
+#[derive(Debug, Clone)]
+pub struct WorkerPoolConfig {
+    workers: usize,
+    capacity: usize,
+    backpressure: Backpressure,
+    drain_timeout: Duration,
+}
+
+impl Default for WorkerPoolConfig {
+    fn default() -> Self {
+        WorkerPoolConfig {
+            workers: 4,
+            capacity: 1024,
+            backpressure: Backpressure::Wait,
+            drain_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl WorkerPoolConfig {
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Number of jobs that can wait for a worker.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub fn with_backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// How long queued and running jobs may take to finish once shutdown drains the pool.
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+}
+
+struct QueuedJob {
+    task: JobFuture,
+    enqueued_at: Instant,
+}
+
+struct Jobs {
+    queued: VecDeque<QueuedJob>,
+    closed: bool,
+}
+
+struct Queue {
+    jobs: Mutex<Jobs>,
+    capacity: usize,
+    backpressure: Backpressure,
+    job_available: Notify,
+    space_available: Notify,
+}
+
+impl Queue {
+    fn lock(&self) -> MutexGuard<'_, Jobs> {
+        self.jobs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queues `job`, or hands it back when the caller has to wait for space first.
+    fn try_push(
+        &self,
+        job: QueuedJob,
+        metrics: &PoolMetrics,
+    ) -> Result<(), (SubmitError, QueuedJob)> {
+        let mut jobs = self.lock();
+        if jobs.closed {
+            return Err((SubmitError::Closed, job));
+        }
+        if jobs.queued.len() >= self.capacity {
+            match self.backpressure {
+                Backpressure::Wait | Backpressure::Reject => return Err((SubmitError::Full, job)),
+                Backpressure::DropOldest => {
+                    jobs.queued.pop_front();
+                    metrics.finished(JobOutcome::Dropped);
+                }
+            }
+        }
+        jobs.queued.push_back(job);
+        drop(jobs);
+        self.job_available.notify_one();
+        Ok(())
+    }
+
+    /// Next job, or `None` once the queue is closed and empty.
+    async fn pop(&self) -> Option<QueuedJob> {
+        loop {
+            // Created before checking, so a `notify_waiters` from `set_closed` cannot be missed.
+            let job_available = self.job_available.notified();
+            {
+                let mut jobs = self.lock();
+                if let Some(job) = jobs.queued.pop_front() {
+                    drop(jobs);
+                    self.space_available.notify_one();
+                    return Some(job);
+                }
+                if jobs.closed {
+                    return None;
+                }
+            }
+            job_available.await;
+        }
+    }
+
+    fn set_closed(&self, closed: bool) {
+        self.lock().closed = closed;
+        if closed {
+            self.job_available.notify_waiters();
+            self.space_available.notify_waiters();
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.lock().queued.len()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum JobOutcome {
+    Success,
+    Failure,
+    Panic,
+    Rejected,
+    Dropped,
+    Abandoned,
+}
+
+impl JobOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            JobOutcome::Success => "success",
+            JobOutcome::Failure => "failure",
+            JobOutcome::Panic => "panic",
+            JobOutcome::Rejected => "rejected",
+            JobOutcome::Dropped => "dropped",
+            JobOutcome::Abandoned => "abandoned",
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PoolMetrics {
+    pool: KeyValue,
+    jobs: Counter<u64>,
+    in_flight: UpDownCounter<i64>,
+    wait: Histogram<f64>,
+    duration: Histogram<f64>,
+}
+
+impl PoolMetrics {
+    fn new(meter: &Meter, name: &str, queue: &Arc<Queue>) -> Self {
+        let pool = KeyValue::new(POOL_NAME, name.to_owned());
+
+        let depth_queue = queue.clone();
+        let depth_pool = pool.clone();
+        meter
+            .u64_observable_gauge("worker_pool.queue.depth")
+            .with_description("Number of jobs waiting for a worker")
+            .with_unit("{job}")
+            .with_callback(move |observer| {
+                observer.observe(depth_queue.len() as u64, std::slice::from_ref(&depth_pool));
+            })
+            .build();
+
+        PoolMetrics {
+            pool,
+            jobs: meter
+                .u64_counter("worker_pool.jobs")
+                .with_description("Number of submitted jobs by outcome")
+                .with_unit("{job}")
+                .build(),
+            in_flight: meter
+                .i64_up_down_counter("worker_pool.jobs.in_flight")
+                .with_description("Number of jobs currently being run by a worker")
+                .with_unit("{job}")
+                .build(),
+            wait: meter
+                .f64_histogram("worker_pool.job.wait")
+                .with_description("Time jobs spent queued before a worker picked them up")
+                .with_unit("s")
+                .build(),
+            duration: meter
+                .f64_histogram("worker_pool.job.duration")
+                .with_description("Time workers spent running jobs")
+                .with_unit("s")
+                .build(),
+        }
+    }
+
+    fn finished(&self, outcome: JobOutcome) {
+        let attributes = [self.pool.clone(), KeyValue::new(JOB_OUTCOME, outcome.as_str())];
+        self.jobs.add(1, &attributes);
+    }
+}
+
+/// Runs fire-and-forget jobs on a fixed number of workers, fed through a bounded queue.
+///
+/// Jobs are submitted through a [`WorkerPoolHandle`]. Once shutdown drains the pool it stops
+/// accepting jobs and works off the queue; whatever is left after the drain timeout, or once
+/// shutdown is cancelled, is abandoned.
+pub struct WorkerPool {
+    name: String,
+    config: WorkerPoolConfig,
+    queue: Arc<Queue>,
+    metrics: PoolMetrics,
+}
+
+impl WorkerPool {
+    pub fn new(name: impl Into<String>, meter: &Meter) -> Self {
+        Self::with_config(name, meter, WorkerPoolConfig::default())
+    }
+
+    pub fn with_config(name: impl Into<String>, meter: &Meter, config: WorkerPoolConfig) -> Self {
+        let name = name.into();
+        let queue = Arc::new(Queue {
+            jobs: Mutex::new(Jobs {
+                queued: VecDeque::with_capacity(config.capacity),
+                closed: true,
+            }),
+            capacity: config.capacity,
+            backpressure: config.backpressure,
+            job_available: Notify::new(),
+            space_available: Notify::new(),
+        });
+        let metrics = PoolMetrics::new(meter, &name, &queue);
+        WorkerPool {
+            name,
+            config,
+            queue,
+            metrics,
+        }
+    }
+
+    pub fn handle(&self) -> WorkerPoolHandle {
+        WorkerPoolHandle {
+            queue: self.queue.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl StarlightService for WorkerPool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn start(&self) -> ServiceResult {
+        self.queue.set_closed(false);
+        Ok(())
+    }
+
+    async fn run(&self, shutdown: ShutdownToken) -> ServiceResult {
+        let stop = shutdown.child_token();
+        let mut workers = JoinSet::new();
+        for _ in 0..self.config.workers {
+            workers.spawn(work(self.queue.clone(), self.metrics.clone(), stop.clone()));
+        }
+
+        shutdown.draining().await;
+        self.queue.set_closed(true);
+        info!("draining worker pool {}, {} jobs queued", self.name, self.queue.len());
+
+        let deadline = async {
+            tokio::select! {
+                _ = tokio::time::sleep(self.config.drain_timeout) => {}
+                _ = shutdown.cancelled() => {}
+            }
+        };
+        let drained = tokio::select! {
+            _ = join_workers(&mut workers) => true,
+            _ = deadline => false,
+        };
+        if !drained {
+            stop.cancel(shutdown.reason().unwrap_or(ShutdownReason::Deadline));
+            join_workers(&mut workers).await;
+        }
+
+        let abandoned = self.queue.lock().queued.drain(..).count();
+        for _ in 0..abandoned {
+            self.metrics.finished(JobOutcome::Abandoned);
+        }
+        if abandoned > 0 {
+            warn!("worker pool {} abandoned {} queued jobs", self.name, abandoned);
+        }
+        Ok(())
+    }
+}
+
+async fn join_workers(workers: &mut JoinSet<()>) {
+    while workers.join_next().await.is_some() {}
+}
+
+async fn work(queue: Arc<Queue>, metrics: PoolMetrics, stop: ShutdownToken) {
+    loop {
+        let job = tokio::select! {
+            job = queue.pop() => job,
+            _ = stop.cancelled() => return,
+        };
+        let Some(job) = job else {
+            return;
+        };
+
+        let pool = std::slice::from_ref(&metrics.pool);
+        metrics.wait.record(job.enqueued_at.elapsed().as_secs_f64(), pool);
+        metrics.in_flight.add(1, pool);
+        let started = Instant::now();
+
+        let mut task = tokio::spawn(job.task);
+        let outcome = tokio::select! {
+            joined = &mut task => match joined {
+                Ok(Ok(())) => JobOutcome::Success,
+                Ok(Err(err)) => {
+                    warn!("worker pool job failed: {}", err);
+                    JobOutcome::Failure
+                }
+                Err(_) => JobOutcome::Panic,
+            },
+            _ = stop.cancelled() => {
+                task.abort();
+                JobOutcome::Abandoned
+            }
+        };
+
+        metrics.in_flight.add(-1, pool);
+        metrics.duration.record(started.elapsed().as_secs_f64(), pool);
+        metrics.finished(outcome);
+        if let JobOutcome::Abandoned = outcome {
+            return;
+        }
+    }
+}
+
+/// Cheap to clone; hand it to request handlers to queue background work.
+#[derive(Clone)]
+pub struct WorkerPoolHandle {
+    queue: Arc<Queue>,
+    metrics: PoolMetrics,
+}
+
+impl WorkerPoolHandle {
+    /// Queues `job`, applying the pool's backpressure policy when the queue is full. The job
+    /// runs inside the span that is current when it is submitted.
+    pub async fn submit(
+        &self,
+        job: impl Future<Output = ServiceResult> + Send + 'static,
+    ) -> Result<(), SubmitError> {
+        let mut job = self.queued(job);
+        loop {
+            let space_available = self.queue.space_available.notified();
+            job = match self.queue.try_push(job, &self.metrics) {
+                Ok(()) => return Ok(()),
+                Err((SubmitError::Full, job)) if self.queue.backpressure == Backpressure::Wait => job,
+                Err((err, _)) => return Err(self.rejected(err)),
+            };
+            space_available.await;
+        }
+    }
+
+    /// Like `submit`, but fails with [`SubmitError::Full`] instead of waiting for space.
+    pub fn try_submit(
+        &self,
+        job: impl Future<Output = ServiceResult> + Send + 'static,
+    ) -> Result<(), SubmitError> {
+        self.queue
+            .try_push(self.queued(job), &self.metrics)
+            .map_err(|(err, _)| self.rejected(err))
+    }
+
+    fn queued(&self, job: impl Future<Output = ServiceResult> + Send + 'static) -> QueuedJob {
+        QueuedJob {
+            task: Box::pin(job.in_current_span()),
+            enqueued_at: Instant::now(),
+        }
+    }
+
+    fn rejected(&self, err: SubmitError) -> SubmitError {
+        self.metrics.finished(JobOutcome::Rejected);
+        err
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Ran = Arc<Mutex<Vec<usize>>>;
+
+    async fn started_pool(config: WorkerPoolConfig) -> Arc<WorkerPool> {
+        let meter = opentelemetry::global::meter("worker_pool_test");
+        let pool = Arc::new(WorkerPool::with_config("test", &meter, config));
+        pool.start().await.unwrap();
+        pool
+    }
+
+    fn running(pool: &Arc<WorkerPool>) -> (ShutdownToken, tokio::task::JoinHandle<ServiceResult>) {
+        let shutdown = ShutdownToken::new();
+        let pool = pool.clone();
+        let token = shutdown.clone();
+        (shutdown, tokio::spawn(async move { pool.run(token).await }))
+    }
+
+    fn recording(ran: &Ran, id: usize) -> impl Future<Output = ServiceResult> + Send + 'static {
+        let ran = ran.clone();
+        async move {
+            ran.lock().unwrap().push(id);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_jobs_before_start() {
+        let meter = opentelemetry::global::meter("worker_pool_test");
+        let pool = WorkerPool::new("test", &meter);
+        let result = pool.handle().try_submit(async { Ok(()) });
+        assert_eq!(result, Err(SubmitError::Closed));
+    }
+
+    #[tokio::test]
+    async fn reject_fails_when_full() {
+        let config = WorkerPoolConfig::default()
+            .with_capacity(1)
+            .with_backpressure(Backpressure::Reject);
+        let handle = started_pool(config).await.handle();
+        assert_eq!(handle.try_submit(async { Ok(()) }), Ok(()));
+        assert_eq!(handle.try_submit(async { Ok(()) }), Err(SubmitError::Full));
+        assert_eq!(
+            handle.submit(async { Ok(()) }).await,
+            Err(SubmitError::Full)
+        );
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_makes_room() {
+        let config = WorkerPoolConfig::default()
+            .with_workers(1)
+            .with_capacity(2)
+            .with_backpressure(Backpressure::DropOldest);
+        let pool = started_pool(config).await;
+        let handle = pool.handle();
+        let ran = Ran::default();
+        for id in 0..3 {
+            handle.try_submit(recording(&ran, id)).unwrap();
+        }
+
+        let (shutdown, run) = running(&pool);
+        shutdown.drain(ShutdownReason::AdminRequest);
+        run.await.unwrap().unwrap();
+        assert_eq!(*ran.lock().unwrap(), [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn wait_blocks_until_a_worker_frees_a_slot() {
+        let config = WorkerPoolConfig::default().with_workers(1).with_capacity(1);
+        let pool = started_pool(config).await;
+        let handle = pool.handle();
+        let ran = Ran::default();
+        handle.submit(recording(&ran, 0)).await.unwrap();
+
+        let waiting = tokio::spawn({
+            let handle = handle.clone();
+            let job = recording(&ran, 1);
+            async move { handle.submit(job).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        let (shutdown, run) = running(&pool);
+        waiting.await.unwrap().unwrap();
+        shutdown.drain(ShutdownReason::AdminRequest);
+        run.await.unwrap().unwrap();
+        assert_eq!(*ran.lock().unwrap(), [0, 1]);
+    }
+
+    #[tokio::test]
+    async fn drain_finishes_queued_jobs_and_closes_the_queue() {
+        let config = WorkerPoolConfig::default().with_workers(2);
+        let pool = started_pool(config).await;
+        let handle = pool.handle();
+        let ran = Ran::default();
+        for id in 0..5 {
+            handle.try_submit(recording(&ran, id)).unwrap();
+        }
+
+        let (shutdown, run) = running(&pool);
+        shutdown.drain(ShutdownReason::AdminRequest);
+        run.await.unwrap().unwrap();
+
+        let mut ran = ran.lock().unwrap().clone();
+        ran.sort();
+        assert_eq!(ran, [0, 1, 2, 3, 4]);
+        assert_eq!(
+            handle.try_submit(async { Ok(()) }),
+            Err(SubmitError::Closed)
+        );
+    }
+
+    #[tokio::test]
+    async fn drain_timeout_abandons_what_is_left() {
+        let config = WorkerPoolConfig::default()
+            .with_workers(1)
+            .with_drain_timeout(Duration::from_millis(50));
+        let pool = started_pool(config).await;
+        let handle = pool.handle();
+        let ran = Ran::default();
+        handle.try_submit(std::future::pending()).unwrap();
+        handle.try_submit(recording(&ran, 1)).unwrap();
+
+        let (shutdown, run) = running(&pool);
+        shutdown.drain(ShutdownReason::AdminRequest);
+        tokio::time::timeout(Duration::from_secs(5), run)
+            .await
+            .expect("the drain timeout stops the pool")
+            .unwrap()
+            .unwrap();
+        assert!(ran.lock().unwrap().is_empty());
+        assert_eq!(pool.queue.len(), 0);
+    }
+}